@@ -1,7 +1,17 @@
-use getopts::Options;
+use getopts::{Matches, Options};
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
 
-use bim_core::clients::{Client, HTTPClient, SpeedtestNetTcpClient};
+#[cfg(feature = "quic")]
+use bim_core::clients::QuicClient;
+use bim_core::clients::{
+    add_resolve, override_host, pin_address, resolve_all, set_latency_probe, set_latency_samples,
+    set_load_timing, set_proxy, set_ramp_up, set_source, set_tls_options, take_connections,
+    Adaptive, AddressFamily, Client, CloudflareClient, HTTP2Client, HTTPClient, Iperf3Client,
+    LibreSpeedClient, LoadTiming, RampUp, SpeedtestNetHttpClient, SpeedtestNetTcpClient,
+    TlsOptions, UdpClient,
+};
 use bim_core::utils::{justify_name, SpeedTestResult};
 
 fn print_usage(program: &str, opts: Options) {
@@ -9,16 +19,141 @@ fn print_usage(program: &str, opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
+/// Option `name` parsed as a `T`, `None` if it was not given.
+fn parse_opt<T: FromStr>(matches: &Matches, name: &str) -> Result<Option<T>, String> {
+    matches
+        .opt_str(name)
+        .map(|value| value.parse().map_err(|_| invalid_opt(name, &value)))
+        .transpose()
+}
+
+/// Option `name` given in seconds, `None` if it was not given.
+fn parse_secs(matches: &Matches, name: &str) -> Result<Option<Duration>, String> {
+    matches
+        .opt_str(name)
+        .map(|value| {
+            value
+                .parse()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| invalid_opt(name, &value))
+        })
+        .transpose()
+}
+
+fn invalid_opt(name: &str, value: &str) -> String {
+    match name.len() {
+        1 => format!("invalid -{name} {value}"),
+        _ => format!("invalid --{name} {value}"),
+    }
+}
+
+/// The udp target bitrate in bits per second asked for with `-b` in Mbps.
+fn parse_bitrate(matches: &Matches) -> Result<u64, String> {
+    let mbps = match parse_opt::<f64>(matches, "b")? {
+        Some(mbps) => mbps,
+        None => return Ok(10_000_000),
+    };
+
+    match (mbps * 1_000_000.0) as u64 {
+        bitrate if mbps.is_finite() && bitrate > 0 => Ok(bitrate),
+        _ => Err(invalid_opt("b", &mbps.to_string())),
+    }
+}
+
+/// The connection ramp-up asked for with `--ramp-up` and `--ramp-step`.
+fn parse_ramp_up(matches: &Matches) -> Result<Option<RampUp>, String> {
+    let threshold = match parse_opt::<f64>(matches, "ramp-up")? {
+        Some(threshold) => threshold,
+        None if matches.opt_present("ramp-step") => {
+            return Err(String::from("--ramp-step needs --ramp-up"))
+        }
+        None => return Ok(None),
+    };
+    let step = parse_secs(matches, "ramp-step")?.unwrap_or(Duration::from_secs(2));
+
+    Ok(Some(RampUp {
+        threshold: threshold / 100.0,
+        step,
+    }))
+}
+
+/// The latency sample count and interval asked for with `--ping-count` and
+/// `--ping-interval`.
+fn parse_latency_samples(matches: &Matches) -> Result<(u32, Duration), String> {
+    let count = parse_opt(matches, "ping-count")?.unwrap_or(6);
+    let interval = parse_opt(matches, "ping-interval")?.unwrap_or(1000);
+
+    Ok((count, Duration::from_millis(interval)))
+}
+
+/// The load test timing asked for with `-d`, `-w`, `--interval`,
+/// `--adaptive` and `--window`.
+fn parse_load_timing(matches: &Matches) -> Result<LoadTiming, String> {
+    let mut timing = LoadTiming::default();
+    if matches.opt_present("window") && !matches.opt_present("adaptive") {
+        return Err(String::from("--window needs --adaptive"));
+    }
+    if let Some(tolerance) = parse_opt::<f64>(matches, "adaptive")? {
+        let window = parse_secs(matches, "window")?.unwrap_or(Duration::from_secs(2));
+        timing.adaptive = Some(Adaptive {
+            tolerance: tolerance / 100.0,
+            window,
+        });
+        timing.duration = Duration::from_secs(30);
+        timing.warmup = Duration::ZERO;
+    }
+    if let Some(duration) = parse_secs(matches, "d")? {
+        timing.duration = duration;
+        if timing.adaptive.is_none() {
+            timing.warmup = timing.duration * 9 / 14;
+        }
+    }
+    if let Some(warmup) = parse_secs(matches, "w")? {
+        timing.warmup = warmup;
+    }
+    if let Some(interval) = parse_opt(matches, "interval")? {
+        timing.interval = Duration::from_millis(interval);
+    }
+
+    Ok(timing)
+}
+
 fn get_client(
     client_name: &str,
     download_url: String,
     upload_url: String,
-    ipv6: bool,
+    family: AddressFamily,
     threads: u8,
+    bitrate: u64,
+    max_redirects: u8,
 ) -> Option<Box<dyn Client>> {
     match client_name {
-        "http" => HTTPClient::build(download_url, upload_url, ipv6, threads),
-        "tcp" => SpeedtestNetTcpClient::build(upload_url, ipv6, threads),
+        "http" => HTTPClient::build(
+            download_url,
+            upload_url,
+            family,
+            threads,
+            false,
+            max_redirects,
+        ),
+        "file" => HTTPClient::build(
+            download_url,
+            upload_url,
+            family,
+            threads,
+            true,
+            max_redirects,
+        ),
+        "http2" => HTTP2Client::build(download_url, upload_url, family, threads),
+        "tcp" => SpeedtestNetTcpClient::build(upload_url, family, threads),
+        "legacy" => SpeedtestNetHttpClient::build(upload_url, family, threads),
+        "librespeed" => LibreSpeedClient::build(download_url, family, threads),
+        "cloudflare" => CloudflareClient::build(download_url, family),
+        "iperf3" => Iperf3Client::build(upload_url, family, threads),
+        "udp" => UdpClient::build(upload_url, family, bitrate),
+        #[cfg(feature = "quic")]
+        "quic" => QuicClient::build(upload_url, family, threads),
         _ => None,
     }
 }
@@ -28,15 +163,120 @@ fn main() {
     let program = args[0].clone();
 
     let mut opts = Options::new();
-    opts.optopt("c", "client", "set test client", "NAME");
+    opts.optopt(
+        "c",
+        "client",
+        "set test client: http, file, http2, tcp, legacy, librespeed, cloudflare, iperf3, udp, quic",
+        "NAME",
+    );
     opts.optflagopt("m", "multi", "enable multi threads", "NUM");
-    opts.optflag("6", "ipv6", "enable ipv6");
+    opts.optflag("6", "ipv6", "use ipv6 only, same as -f v6");
+    opts.optopt(
+        "f",
+        "family",
+        "set address family: v4, v6, prefer-v4, prefer-v6 or both, default v4",
+        "POLICY",
+    );
+    opts.optopt(
+        "d",
+        "duration",
+        "set load test duration, or the longest one when adaptive, default 14",
+        "SECS",
+    );
+    opts.optopt(
+        "w",
+        "warmup",
+        "set warm-up left out of the speed, default 9/14 of the duration",
+        "SECS",
+    );
+    opts.optopt(
+        "",
+        "interval",
+        "set load sampling interval, default 500",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "adaptive",
+        "end the load test once throughput varies less than PCT, default duration 30",
+        "PCT",
+    );
+    opts.optopt(
+        "",
+        "window",
+        "set adaptive throughput window, default 2",
+        "SECS",
+    );
+    opts.optopt(
+        "",
+        "ramp-up",
+        "add connections while each raises throughput by more than PCT, up to -m or 32",
+        "PCT",
+    );
+    opts.optopt(
+        "",
+        "ramp-step",
+        "set time to measure each added connection, default 2",
+        "SECS",
+    );
+    opts.optopt("b", "bitrate", "set udp target bitrate, default 10", "MBPS");
+    opts.optopt(
+        "r",
+        "redirects",
+        "set http redirects to follow, default 5",
+        "NUM",
+    );
+    opts.optopt(
+        "x",
+        "proxy",
+        "set proxy: socks5://, socks5h:// or http:// URL, default from *_proxy",
+        "URL",
+    );
+    opts.optopt("", "source", "bind sockets to local IP address", "ADDR");
+    opts.optopt(
+        "",
+        "interface",
+        "bind sockets to network interface, linux only",
+        "NAME",
+    );
+    opts.optmulti(
+        "",
+        "resolve",
+        "connect to ADDR for HOST and PORT without DNS",
+        "HOST:PORT:ADDR",
+    );
+    opts.optopt(
+        "",
+        "host",
+        "send NAME as Host and TLS SNI, connecting to the URL address",
+        "NAME",
+    );
+    opts.optmulti("", "cacert", "trust CA certificates from PEM file", "FILE");
+    opts.optflag("k", "insecure", "skip TLS certificate verification");
+    opts.optopt("", "cert", "set TLS client certificate PEM file", "FILE");
+    opts.optopt("", "key", "set TLS client private key PEM file", "FILE");
+    opts.optflag("a", "all-addresses", "test every resolved address in turn");
+    opts.optflag("p", "ping", "only measure latency");
+    opts.optopt(
+        "",
+        "ping-type",
+        "set latency probe: tcp, http, icmp or udp, default per client",
+        "TYPE",
+    );
+    opts.optopt("", "ping-count", "set latency samples, default 6", "NUM");
+    opts.optopt(
+        "",
+        "ping-interval",
+        "set time between latency samples, default 1000",
+        "MS",
+    );
+    opts.optflag("j", "json", "print result as json");
     opts.optflag("n", "name", "print justified name");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => {
-            println!("{}\n", f.to_string());
+            println!("{}\n", f);
             print_usage(&program, opts);
             return;
         }
@@ -69,26 +309,239 @@ fn main() {
         return;
     }
 
-    let download_url = dl.unwrap().clone();
-    let upload_url = ul.unwrap().clone();
-    let ipv6 = matches.opt_present("6");
+    let mut download_url = dl.unwrap().clone();
+    let mut upload_url = ul.unwrap().clone();
+    let family = match matches.opt_str("f").map(|value| value.parse()) {
+        Some(Ok(family)) => family,
+        Some(Err(e)) => {
+            println!("{}\n", e);
+            print_usage(&program, opts);
+            return;
+        }
+        None if matches.opt_present("6") => AddressFamily::V6,
+        None => AddressFamily::V4,
+    };
+
+    let client_name = matches.opt_str("c").unwrap_or("http".to_string());
+
+    let ramp_up = match parse_ramp_up(&matches) {
+        Ok(ramp_up) => ramp_up,
+        Err(e) => {
+            println!("{}\n", e);
+            print_usage(&program, opts);
+            return;
+        }
+    };
+    if let Some(ramp_up) = ramp_up {
+        // Only the clients that open a connection per thread ramp them up.
+        if !matches!(
+            client_name.as_str(),
+            "http" | "file" | "tcp" | "legacy" | "librespeed"
+        ) {
+            println!("{client_name} does not ramp up connections\n");
+            print_usage(&program, opts);
+            return;
+        }
+        if let Err(e) = set_ramp_up(ramp_up) {
+            println!("{}\n", e);
+            print_usage(&program, opts);
+            return;
+        }
+    }
 
     let theads = matches
         .opt_str("m")
         .and_then(|value| value.parse().ok())
-        .unwrap_or(1);
+        .unwrap_or(if ramp_up.is_some() { 32 } else { 1 });
+
+    let bitrate = match parse_bitrate(&matches) {
+        Ok(bitrate) => bitrate,
+        Err(e) => {
+            println!("{}\n", e);
+            print_usage(&program, opts);
+            return;
+        }
+    };
+
+    let max_redirects = matches
+        .opt_str("r")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(5);
+
+    if let Some(proxy) = matches.opt_str("x") {
+        if let Err(e) = set_proxy(&proxy) {
+            println!("{}\n", e);
+            print_usage(&program, opts);
+            return;
+        }
+    }
+
+    let latency_samples = parse_latency_samples(&matches)
+        .and_then(|(count, interval)| set_latency_samples(count, interval));
+    if let Err(e) = latency_samples {
+        println!("{}\n", e);
+        print_usage(&program, opts);
+        return;
+    }
+
+    if let Err(e) = parse_load_timing(&matches).and_then(set_load_timing) {
+        println!("{}\n", e);
+        print_usage(&program, opts);
+        return;
+    }
+
+    if let Some(probe) = matches.opt_str("ping-type") {
+        if let Err(e) = probe.parse().and_then(set_latency_probe) {
+            println!("{}\n", e);
+            print_usage(&program, opts);
+            return;
+        }
+    }
+
+    let source = matches.opt_str("source");
+    let interface = matches.opt_str("interface");
+    if source.is_some() || interface.is_some() {
+        if let Err(e) = set_source(source.as_deref(), interface.as_deref()) {
+            println!("{}\n", e);
+            print_usage(&program, opts);
+            return;
+        }
+    }
+
+    for entry in matches.opt_strs("resolve") {
+        if let Err(e) = add_resolve(&entry) {
+            println!("{}\n", e);
+            print_usage(&program, opts);
+            return;
+        }
+    }
+
+    if let Some(host) = matches.opt_str("host") {
+        let urls = override_host(&download_url, &host)
+            .and_then(|dl| Ok((dl, override_host(&upload_url, &host)?)));
+        match urls {
+            Ok((dl, ul)) => {
+                download_url = dl;
+                upload_url = ul;
+            }
+            Err(e) => {
+                println!("{}\n", e);
+                print_usage(&program, opts);
+                return;
+            }
+        }
+    }
+
+    let client_cert = match (matches.opt_str("cert"), matches.opt_str("key")) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => {
+            println!("--cert and --key must be given together\n");
+            print_usage(&program, opts);
+            return;
+        }
+    };
+    let tls_options = TlsOptions {
+        ca_files: matches.opt_strs("cacert"),
+        insecure: matches.opt_present("k"),
+        client_cert,
+    };
+    if let Err(e) = set_tls_options(tls_options) {
+        println!("{}\n", e);
+        print_usage(&program, opts);
+        return;
+    }
 
     #[cfg(debug_assertions)]
     env_logger::init();
 
-    let client_name = matches.opt_str("c").unwrap_or("http".to_string());
-    let r = match get_client(&client_name, download_url, upload_url, ipv6, theads) {
+    if matches.opt_present("x") && matches!(client_name.as_str(), "udp" | "quic") {
+        println!("{client_name} can not go through a proxy\n");
+        print_usage(&program, opts);
+        return;
+    }
+    let run = |family| match get_client(
+        &client_name,
+        download_url.clone(),
+        upload_url.clone(),
+        family,
+        theads,
+        bitrate,
+        max_redirects,
+    ) {
         Some(mut client) => {
-            let _ = (*client).run();
-            client.result()
+            match matches.opt_present("p") {
+                true => {
+                    let _ = (*client).ping();
+                }
+                false => {
+                    let _ = (*client).run();
+                }
+            }
+            let mut r = client.result();
+            r.set_connections(take_connections());
+            r
         }
         None => SpeedTestResult::build(0.0, "失败".to_string(), 0.0, "失败".to_string(), 0.0, 0.0),
     };
 
-    println!("{}", r.text());
+    // The URL whose host the client connects to first.
+    let target_url = match client_name.as_str() {
+        "http" | "file" | "http2" | "librespeed" | "cloudflare" => &download_url,
+        _ => &upload_url,
+    };
+
+    // Resolve every family before pinning any address to the host. A family
+    // without addresses is still run by name to report its failure.
+    let all_addresses = matches.opt_present("a");
+    let runs: Vec<_> = family
+        .families()
+        .into_iter()
+        .map(|family| match all_addresses {
+            true => (family, resolve_all(target_url, family).ok()),
+            false => (family, None),
+        })
+        .collect();
+
+    let mut results = vec![];
+    for (family, ips) in runs {
+        match ips {
+            Some(ips) => {
+                for ip in ips {
+                    if let Err(e) = pin_address(target_url, ip) {
+                        println!("{}", e);
+                        return;
+                    }
+                    results.push((ip.to_string(), run(family)));
+                }
+            }
+            None => results.push((family.to_string(), run(family))),
+        }
+    }
+
+    // With both families or every address, each result is labelled with
+    // its family or address.
+    match (matches.opt_present("j"), results.as_slice()) {
+        (true, [(_, r)]) if !all_addresses => {
+            println!("{}", serde_json::to_string(r).unwrap())
+        }
+        (false, [(_, r)]) if !all_addresses => println!("{}", r.text()),
+        (true, results) => {
+            let results: serde_json::Map<String, serde_json::Value> = results
+                .iter()
+                .map(|(label, r)| (label.clone(), serde_json::to_value(r).unwrap()))
+                .collect();
+            println!("{}", serde_json::to_string(&results).unwrap());
+        }
+        (false, results) => {
+            let width = results
+                .iter()
+                .map(|(label, _)| label.len())
+                .max()
+                .unwrap_or(0);
+            for (label, r) in results {
+                println!("{},{}", justify_name(label, width as u8, true), r.text());
+            }
+        }
+    }
 }
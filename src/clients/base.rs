@@ -1,65 +1,232 @@
-use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
-use std::sync::{Arc, Barrier, RwLock};
-use std::thread;
-use std::time::{Duration, Instant};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Barrier, Mutex, OnceLock, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
 #[cfg(debug_assertions)]
 use log::debug;
 
-use rustls::{OwnedTrustAnchor, RootCertStore};
 use url::Url;
 
-use crate::utils::SpeedTestResult;
+use crate::clients::probe::Prober;
+use crate::clients::{proxy, resolve, tls};
+use crate::utils::{ConnectionInfo, FlowReport, FlowStats, LatencyStats, SpeedTestResult};
 
-pub trait GenericStream: Read + Write {}
+static CONNECTIONS: Mutex<Vec<ConnectionInfo>> = Mutex::new(vec![]);
+static LATENCY_SAMPLES: OnceLock<(u32, Duration)> = OnceLock::new();
+static LOAD_TIMING: OnceLock<LoadTiming> = OnceLock::new();
+static RAMP_UP: OnceLock<RampUp> = OnceLock::new();
 
-impl<T: Read + Write> GenericStream for T {}
+/// A stall of this long in total marks the test as 断流.
+const STALL_LIMIT: Duration = Duration::from_secs(3);
 
-pub fn get_address(url: &Url, ipv6: bool) -> Option<SocketAddr> {
-    let host = url.host_str()?;
-    let port = url.port_or_known_default()?;
+/// At most this many connections are kept in the result.
+const MAX_CONNECTIONS: usize = 256;
+
+pub trait GenericStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> GenericStream for T {}
+
+/// Which IP versions to test over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+    PreferV4,
+    PreferV6,
+    /// Run the test over each family in turn, see `families`. A single
+    /// lookup takes whichever address comes first.
+    Both,
+}
+
+impl AddressFamily {
+    /// The families to run a whole test over, one after the other.
+    pub fn families(self) -> Vec<AddressFamily> {
+        match self {
+            AddressFamily::Both => vec![AddressFamily::V4, AddressFamily::V6],
+            family => vec![family],
+        }
+    }
+
+    /// The `addresses` of this family, preferred ones first.
+    fn filter(self, addresses: &[SocketAddr]) -> Vec<SocketAddr> {
+        let v4 = addresses.iter().filter(|addr| addr.is_ipv4());
+        let v6 = addresses.iter().filter(|addr| addr.is_ipv6());
+
+        match self {
+            AddressFamily::V4 => v4.copied().collect(),
+            AddressFamily::V6 => v6.copied().collect(),
+            AddressFamily::PreferV4 => v4.chain(v6).copied().collect(),
+            AddressFamily::PreferV6 => v6.chain(v4).copied().collect(),
+            AddressFamily::Both => addresses.to_vec(),
+        }
+    }
+}
+
+impl FromStr for AddressFamily {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4" | "v4" => Ok(AddressFamily::V4),
+            "6" | "v6" => Ok(AddressFamily::V6),
+            "prefer-v4" => Ok(AddressFamily::PreferV4),
+            "prefer-v6" => Ok(AddressFamily::PreferV6),
+            "both" => Ok(AddressFamily::Both),
+            _ => Err(format!("unknown address family {s}")),
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AddressFamily::V4 => "IPv4",
+            AddressFamily::V6 => "IPv6",
+            AddressFamily::PreferV4 => "prefer IPv4",
+            AddressFamily::PreferV6 => "prefer IPv6",
+            AddressFamily::Both => "both",
+        };
+        write!(f, "{name}")
+    }
+}
+
+pub fn get_address(url: &Url, family: AddressFamily) -> Option<SocketAddr> {
+    get_addresses(url, family).first().copied()
+}
+
+/// Every address of the host of `url` in `family`, in the order
+/// `get_address` would pick them.
+pub fn get_addresses(url: &Url, family: AddressFamily) -> Vec<SocketAddr> {
+    let pinned = resolve::pinned_host(url);
+    let host = match (&pinned, url.host_str()) {
+        (Some(host), _) => host.as_str(),
+        (None, Some(host)) => host,
+        (None, None) => return vec![],
+    };
+    let port = match url.port_or_known_default() {
+        Some(port) => port,
+        None => return vec![],
+    };
+
+    // Only the port of the address reaches a socks5h or HTTP proxy, which
+    // is sent the host name instead.
+    if pinned.is_none() && proxy::resolves_remotely(url) {
+        let ip = match family {
+            AddressFamily::V6 | AddressFamily::PreferV6 => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+            _ => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        };
+        return vec![SocketAddr::new(ip, port)];
+    }
 
     let host_port = format!("{host}:{port}");
-    let addresses = host_port.to_socket_addrs().ok()?;
+    let addresses: Vec<SocketAddr> = match host_port.to_socket_addrs() {
+        Ok(addresses) => addresses.collect(),
+        Err(_) => return vec![],
+    };
 
-    addresses
-        .into_iter()
-        .find(|addr| (addr.is_ipv6() && ipv6) || (addr.is_ipv4() && !ipv6))
+    #[cfg(debug_assertions)]
+    debug!("Resolve {host_port} {addresses:?}");
+
+    let mut addresses_in_family = family.filter(&addresses);
+    addresses_in_family.dedup();
+
+    // A pinned address is used even if it is not of the family asked for.
+    match pinned {
+        Some(_) if addresses_in_family.is_empty() => addresses,
+        _ => addresses_in_family,
+    }
 }
 
 pub fn make_connection(address: &SocketAddr, url: &Url) -> Result<Box<dyn GenericStream>, String> {
-    let ssl = if url.scheme() == "https" { true } else { false };
-    let mut retry = 3;
+    make_alpn_connection(address, url, vec![])
+}
+
+/// Same as `make_connection`, offering `alpn` protocols in the TLS handshake.
+pub fn make_alpn_connection(
+    address: &SocketAddr,
+    url: &Url,
+    alpn: Vec<Vec<u8>>,
+) -> Result<Box<dyn GenericStream>, String> {
+    let (r, info) = open_connection(address, url, alpn);
+    record_connection(info);
+    r
+}
 
-    let mut root_store = RootCertStore::empty();
-    root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
-        |ta| {
-            OwnedTrustAnchor::from_subject_spki_name_constraints(
-                ta.subject,
-                ta.spki,
-                ta.name_constraints,
-            )
-        },
-    ));
-
-    let config = rustls::ClientConfig::builder()
-        .with_safe_defaults()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
+/// Same as `make_connection`, but left out of the connections in the
+/// result, for requests that only prepare the test.
+pub fn make_probe_connection(
+    address: &SocketAddr,
+    url: &Url,
+) -> Result<Box<dyn GenericStream>, String> {
+    open_connection(address, url, vec![]).0
+}
+
+/// Connect to `address` for `url`, returning the details of the connection
+/// along with it, or of the last attempt when none succeeded.
+fn open_connection(
+    address: &SocketAddr,
+    url: &Url,
+    alpn: Vec<Vec<u8>>,
+) -> (Result<Box<dyn GenericStream>, String>, ConnectionInfo) {
+    let ssl = url.scheme() == "https";
+    let mut retry = 3;
+    let alpn_required = !alpn.is_empty();
 
+    let config = tls::client_config(alpn);
     let server_name = url.host_str().unwrap().try_into().unwrap();
-    let conn = rustls::ClientConnection::new(Arc::new(config), server_name).unwrap();
+    let mut conn = rustls::ClientConnection::new(config, server_name).unwrap();
 
+    let mut failure = ConnectionInfo::new(0.0);
     while retry > 0 {
-        if let Ok(stream) = TcpStream::connect_timeout(&address, Duration::from_micros(1_000_000)) {
+        let now = Instant::now();
+        let r = proxy::connect(address, url, Duration::from_micros(1_000_000));
+        if let Err(e) = &r {
+            failure.connect = now.elapsed().as_micros() as f64 / 1_000.0;
+            failure.error = Some(connection_error(e));
+        }
+        if let Ok(mut stream) = r {
+            let mut info = ConnectionInfo::new(now.elapsed().as_micros() as f64 / 1_000.0);
+
             #[cfg(debug_assertions)]
             debug!("TCP connected");
 
             let _r = stream.set_write_timeout(Some(Duration::from_secs(3)));
             let _r = stream.set_read_timeout(Some(Duration::from_secs(3)));
             if !ssl {
-                return Ok(Box::new(stream));
+                return (Ok(Box::new(stream)), info);
+            }
+
+            // Finish the handshake here so a bad certificate or a stalled TLS
+            // terminator is reported as such, and timed apart from the link.
+            let now = Instant::now();
+            while conn.is_handshaking() {
+                if let Err(e) = conn.complete_io(&mut stream) {
+                    #[cfg(debug_assertions)]
+                    debug!("SSL handshake {e}");
+
+                    let e = connection_error(&e);
+                    info.error = Some(e.clone());
+                    return (Err(format!("握手失败 {e}")), info);
+                }
+            }
+            info.handshake = Some(now.elapsed().as_micros() as f64 / 1_000.0);
+            info.protocol = conn.protocol_version().map(|v| format!("{v:?}"));
+            info.cipher_suite = conn
+                .negotiated_cipher_suite()
+                .map(|c| format!("{:?}", c.suite()));
+            info.alpn = conn
+                .alpn_protocol()
+                .map(|p| String::from_utf8_lossy(p).into_owned());
+
+            // rustls only rejects a protocol that was not offered, not none.
+            if alpn_required && info.alpn.is_none() {
+                info.error = Some(String::from("no ALPN protocol agreed"));
+                return (Err(String::from("握手失败 no ALPN protocol agreed")), info);
             }
 
             let tls = rustls::StreamOwned::new(conn, stream);
@@ -67,18 +234,42 @@ pub fn make_connection(address: &SocketAddr, url: &Url) -> Result<Box<dyn Generi
             #[cfg(debug_assertions)]
             debug!("SSL connected");
 
-            return Ok(Box::new(tls));
+            return (Ok(Box::new(tls)), info);
         }
 
         retry -= 1;
     }
     
-    Err(String::from("连接失败"))
+    let e = format!("连接失败 {}", failure.error.as_deref().unwrap_or_default());
+    (Err(e), failure)
 }
 
-pub fn request_tcp_ping(address: &SocketAddr) -> u128 {
+/// The text of a connection error, naming a socket timeout as such rather
+/// than by the `EAGAIN` it shows up as.
+fn connection_error(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => String::from("timed out"),
+        _ => e.to_string(),
+    }
+}
+
+/// Keep the details of a new connection for the result, up to
+/// `MAX_CONNECTIONS` so a test that reconnects often stays bounded.
+fn record_connection(info: ConnectionInfo) {
+    let mut connections = CONNECTIONS.lock().unwrap();
+    if connections.len() < MAX_CONNECTIONS {
+        connections.push(info);
+    }
+}
+
+/// Details of every connection made so far, leaving none behind.
+pub fn take_connections() -> Vec<ConnectionInfo> {
+    std::mem::take(&mut *CONNECTIONS.lock().unwrap())
+}
+
+pub fn request_tcp_ping(address: &SocketAddr, url: &Url) -> u128 {
     let now = Instant::now();
-    let r = TcpStream::connect_timeout(&address, Duration::from_micros(1_000_000));
+    let r = proxy::connect(address, url, Duration::from_micros(1_000_000));
     let used = now.elapsed().as_micros();
     match r {
         Ok(_) => used,
@@ -91,20 +282,250 @@ pub fn request_tcp_ping(address: &SocketAddr) -> u128 {
     }
 }
 
+/// `host:port` of `url` for the `Host` header, the port always written out.
+pub fn host_port(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap(),
+        url.port_or_known_default().unwrap()
+    )
+}
+
+/// A value that changes between requests, to keep caches out of the way.
+pub fn random_query() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// Time from sending `GET url?query` to the first response byte, with the
+/// TCP and TLS handshakes already done so only the HTTP round trip is counted.
+pub fn request_http_ping(address: &SocketAddr, url: &Url, query: &str) -> u128 {
+    let mut buffer = [0; 1024];
+
+    let mut stream = match make_connection(address, url) {
+        Ok(s) => s,
+        Err(_) => return 0,
+    };
+
+    let request_head = format!(
+        "GET {}?{} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bim/1.0\r\nConnection: close\r\n\r\n",
+        url.path(),
+        query,
+        host_port(url),
+    )
+    .into_bytes();
+
+    let now = Instant::now();
+    if stream.write_all(&request_head).is_err() {
+        return 0;
+    }
+    match stream.read(&mut buffer) {
+        Ok(size) if size > 0 => now.elapsed().as_micros(),
+        _r => {
+            #[cfg(debug_assertions)]
+            debug!("Ping {_r:?}");
+
+            0
+        }
+    }
+}
+
+/// Take `count` latency samples `interval` apart instead of 6 one second
+/// apart. Only the first call has an effect.
+pub fn set_latency_samples(count: u32, interval: Duration) -> Result<(), String> {
+    if count == 0 {
+        return Err(String::from("latency sample count must be positive"));
+    }
+
+    LATENCY_SAMPLES
+        .set((count, interval))
+        .map_err(|_| String::from("latency samples already set"))
+}
+
+/// Run the latency probe chosen with `set_latency_probe` against `address`,
+/// or else the client's own `probe`, which gets the sequence number of the
+/// sample and returns the round trip time in microseconds or 0 if it failed.
+pub fn measure_latency<F>(address: &SocketAddr, url: &Url, mut probe: F) -> LatencyStats
+where
+    F: FnMut(u64) -> u128,
+{
+    let (count, interval) = *LATENCY_SAMPLES.get_or_init(|| (6, Duration::from_secs(1)));
+    let mut prober = Prober::build(address, url);
+    let mut rtts = vec![];
+
+    for seq in 0..count {
+        if seq > 0 {
+            thread::sleep(interval);
+        }
+
+        let rtt = match &mut prober {
+            Some(prober) => prober.probe(seq as u64),
+            None => probe(seq as u64),
+        };
+        if rtt > 0 {
+            rtts.push(rtt as f64 / 1_000.0);
+        }
+    }
+
+    let stats = LatencyStats::build(&rtts, count);
+
+    #[cfg(debug_assertions)]
+    debug!("{stats}");
+
+    stats
+}
+
+/// How long a load test runs, how much of its start is left out of the
+/// speed, and how often the transferred bytes are sampled.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadTiming {
+    /// The whole test, or the longest it may take in adaptive mode.
+    pub duration: Duration,
+    pub warmup: Duration,
+    pub interval: Duration,
+    pub adaptive: Option<Adaptive>,
+}
+
+/// End a load test early once the throughput over `window` stays within
+/// `tolerance`, a fraction of its mean, for another `window`.
+#[derive(Debug, Clone, Copy)]
+pub struct Adaptive {
+    pub tolerance: f64,
+    pub window: Duration,
+}
+
+impl Default for LoadTiming {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(14),
+            warmup: Duration::from_secs(9),
+            interval: Duration::from_millis(500),
+            adaptive: None,
+        }
+    }
+}
+
+/// Use `timing` for every load test instead of the default 14 s with the
+/// first 9 s left out. Only the first call has an effect.
+pub fn set_load_timing(timing: LoadTiming) -> Result<(), String> {
+    if timing.interval.is_zero() {
+        return Err(String::from("sampling interval must be positive"));
+    }
+    if timing.warmup >= timing.duration {
+        return Err(String::from("warm-up must be shorter than the duration"));
+    }
+    if let Some(adaptive) = timing.adaptive {
+        if adaptive.tolerance <= 0.0 {
+            return Err(String::from("tolerance must be positive"));
+        }
+        if adaptive.window < timing.interval {
+            return Err(String::from(
+                "window must be at least the sampling interval",
+            ));
+        }
+    }
+
+    LOAD_TIMING
+        .set(timing)
+        .map_err(|_| String::from("load timing already set"))
+}
+
+/// The duration given to `set_load_timing`, for tests that do not sample
+/// through a `LoadCounter`.
+pub fn load_duration() -> Option<Duration> {
+    LOAD_TIMING.get().map(|timing| timing.duration)
+}
+
+/// Add connections one at a time while each raises the throughput measured
+/// over `step` by more than `threshold`, a fraction of it.
+#[derive(Debug, Clone, Copy)]
+pub struct RampUp {
+    pub threshold: f64,
+    pub step: Duration,
+}
+
+/// Ramp up the connections of the clients that open one per thread, with
+/// their thread count as the limit. Only the first call has an effect.
+pub fn set_ramp_up(ramp_up: RampUp) -> Result<(), String> {
+    if ramp_up.threshold <= 0.0 {
+        return Err(String::from("ramp-up threshold must be positive"));
+    }
+    if ramp_up.step.is_zero() {
+        return Err(String::from("ramp-up step must be positive"));
+    }
+
+    RAMP_UP
+        .set(ramp_up)
+        .map_err(|_| String::from("ramp-up already set"))
+}
+
+/// The bytes of one worker on a cache line of its own, so workers counting
+/// at the same time do not contend.
+#[repr(align(64))]
+#[derive(Default)]
+struct Slot {
+    bytes: AtomicU64,
+    reconnects: AtomicU32,
+    died: AtomicBool,
+    /// Set for a worker that never connected.
+    unconnected: AtomicBool,
+    /// Set for a worker ramp-up found to add nothing, to end it alone.
+    stopped: AtomicBool,
+    /// Bytes at the last sample and the time without progress since the
+    /// measurement started, kept by `count`.
+    sampled: AtomicU64,
+    stall: AtomicU64,
+}
+
 pub struct LoadCounter {
-    counter: RwLock<u64>,
+    timing: LoadTiming,
+    ramp_up: Option<RampUp>,
+    saturation: RwLock<Option<u8>>,
+    slots: RwLock<Vec<Arc<Slot>>>,
     stater: Barrier,
-    ender: RwLock<bool>,
+    ender: AtomicBool,
     results: RwLock<Vec<(u64, u128)>>,
+    failure: RwLock<Option<String>>,
 }
 
 impl LoadCounter {
     pub fn new(threads: u8) -> Self {
         Self {
-            counter: RwLock::new(0),
+            timing: LOAD_TIMING.get().copied().unwrap_or_default(),
+            ramp_up: None,
+            saturation: RwLock::new(None),
+            slots: RwLock::new(vec![]),
             stater: Barrier::new((threads + 1) as usize),
-            ender: RwLock::new(false),
+            ender: AtomicBool::new(false),
             results: RwLock::new(vec![]),
+            failure: RwLock::new(None),
+        }
+    }
+
+    /// A counter for workers started by `start`, which ramps them up one at
+    /// a time if `set_ramp_up` was called.
+    pub fn with_ramp_up(threads: u8) -> Self {
+        match RAMP_UP.get() {
+            Some(ramp_up) => Self {
+                ramp_up: Some(*ramp_up),
+                // Each worker passes the barrier along with `start` alone.
+                stater: Barrier::new(2),
+                ..Self::new(threads)
+            },
+            None => Self::new(threads),
+        }
+    }
+
+    /// A handle for one more worker, counting into a slot of its own.
+    pub fn worker(self: &Arc<Self>) -> LoadWorker {
+        let slot = Arc::new(Slot::default());
+        self.slots.write().unwrap().push(slot.clone());
+
+        LoadWorker {
+            counter: self.clone(),
+            slot,
         }
     }
 
@@ -112,51 +533,239 @@ impl LoadCounter {
         self.stater.wait();
     }
 
+    /// Start `threads` workers with `spawn`, or in ramp-up mode as many as
+    /// keep raising the throughput, and wait for them to connect.
+    pub fn start<T>(
+        self: &Arc<Self>,
+        threads: u8,
+        mut spawn: impl FnMut(LoadWorker) -> JoinHandle<T>,
+    ) -> Vec<JoinHandle<T>> {
+        let mut tasks = vec![];
+
+        let ramp_up = match self.ramp_up {
+            Some(r) => r,
+            None => {
+                for _ in 0..threads {
+                    tasks.push(spawn(self.worker()));
+                    thread::sleep(Duration::from_millis(250));
+                }
+                self.wait();
+                return tasks;
+            }
+        };
+
+        let mut last_speed = 0.0;
+        loop {
+            tasks.push(spawn(self.worker()));
+            self.wait();
+
+            let c_start = self.total();
+            let now = Instant::now();
+            thread::sleep(ramp_up.step);
+            let c_end = self.total();
+            let speed = ((c_end - c_start) * 8) as f64 / now.elapsed().as_micros() as f64;
+
+            #[cfg(debug_assertions)]
+            debug!("Ramp up {} connections {speed:.1}Mbps", tasks.len());
+
+            if speed <= last_speed * (1.0 + ramp_up.threshold) {
+                // The last connection added nothing, the one before saturated.
+                // Unless it is the only one, it is stopped and left out.
+                if tasks.len() > 1 {
+                    if let Some(slot) = self.slots.write().unwrap().pop() {
+                        slot.stopped.store(true, Ordering::Relaxed);
+                    }
+                }
+                *self.saturation.write().unwrap() = Some(tasks.len().max(2) as u8 - 1);
+                return tasks;
+            }
+            if tasks.len() >= threads as usize {
+                *self.saturation.write().unwrap() = Some(tasks.len() as u8);
+                return tasks;
+            }
+            last_speed = speed;
+        }
+    }
+
+    /// The connection count the link saturated at, in ramp-up mode.
+    pub fn saturation(&self) -> Option<u8> {
+        *self.saturation.read().unwrap()
+    }
+
     pub fn end(&self) {
-        let mut e = self.ender.write().unwrap();
-        *e = true;
+        self.ender.store(true, Ordering::Release);
     }
 
     pub fn is_end(&self) -> bool {
-        let e = self.ender.read().unwrap();
-        *e
+        self.ender.load(Ordering::Acquire)
     }
 
-    pub fn increase(&self, count: u64) {
-        let mut c = self.counter.write().unwrap();
-        *c += count;
+    fn total(&self) -> u64 {
+        self.slots
+            .read()
+            .unwrap()
+            .iter()
+            .map(|slot| slot.bytes.load(Ordering::Relaxed))
+            .sum()
     }
 
     pub fn count(&self, time_passed: u128) {
-        let c = { *self.counter.read().unwrap() };
-
+        let slots = self.slots.read().unwrap();
         let mut results = self.results.write().unwrap();
+
+        let last = results.last().map(|(_, t)| *t);
+        let mut c = 0;
+        for slot in slots.iter() {
+            let bytes = slot.bytes.load(Ordering::Relaxed);
+            let stalled = bytes == slot.sampled.swap(bytes, Ordering::Relaxed);
+            if let (true, Some(t)) = (stalled, last) {
+                if !slot.died.load(Ordering::Relaxed) {
+                    slot.stall
+                        .fetch_add((time_passed - t) as u64, Ordering::Relaxed);
+                }
+            }
+            c += bytes;
+        }
+
         results.push((c, time_passed));
     }
 
+    /// What each worker transferred, with how fairly they shared the link.
+    pub fn flows(&self) -> FlowReport {
+        let flows = self
+            .slots
+            .read()
+            .unwrap()
+            .iter()
+            .map(|slot| FlowStats {
+                bytes: slot.bytes.load(Ordering::Relaxed),
+                stall: slot.stall.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+                reconnects: slot.reconnects.load(Ordering::Relaxed),
+                died_early: slot.died.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        FlowReport::build(flows)
+    }
+
+    /// Sample the transferred bytes every interval until the test duration
+    /// has passed, or in adaptive mode until the throughput is stable,
+    /// returning the time passed in microseconds.
+    pub fn measure(&self) -> u128 {
+        let duration = self.timing.duration.as_micros();
+        let mut time_passed = 0;
+
+        // Anything sent while ramping up is left out of the speed.
+        self.count(0);
+
+        let now = Instant::now();
+        while time_passed < duration {
+            thread::sleep(self.timing.interval);
+            time_passed = now.elapsed().as_micros();
+
+            self.count(time_passed);
+
+            if let Some(adaptive) = &self.timing.adaptive {
+                if self.is_stable(adaptive) {
+                    #[cfg(debug_assertions)]
+                    debug!("Stable after {time_passed} us");
+
+                    break;
+                }
+            }
+        }
+        time_passed
+    }
+
+    /// Whether every rolling throughput over the last window is within the
+    /// tolerance of their mean.
+    fn is_stable(&self, adaptive: &Adaptive) -> bool {
+        let results = self.results.read().unwrap();
+        let window = adaptive.window.as_micros();
+        let time_passed = match results.last() {
+            Some((_, t)) => *t,
+            None => return false,
+        };
+        if time_passed < self.timing.warmup.as_micros() + 2 * window {
+            return false;
+        }
+
+        // Throughput over the window ending at each recent sample.
+        let rates: Vec<f64> = results
+            .iter()
+            .filter(|(_, t)| *t + window >= time_passed)
+            .filter_map(|(c, t)| {
+                let (c_start, t_start) = results.iter().rev().find(|(_, s)| s + window <= *t)?;
+                Some((c - c_start) as f64 / (t - t_start) as f64)
+            })
+            .collect();
+        if rates.len() < 2 {
+            return false;
+        }
+
+        let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+        let deviation = rates.iter().map(|r| (r - mean).abs()).fold(0.0, f64::max);
+
+        mean > 0.0 && deviation <= adaptive.tolerance * mean
+    }
+
+    /// Speed in Mbps from the first sample after the warm-up to the last. In
+    /// adaptive mode only the last two windows count.
     pub fn speed(&self) -> f64 {
-        let (c18, t18) = self.results.read().unwrap()[17];
-        let (c28, t28) = self.results.read().unwrap()[27];
+        let results = self.results.read().unwrap();
+        let time_passed = results.last().map(|(_, t)| *t).unwrap_or_default();
+        let warmup = match &self.timing.adaptive {
+            Some(adaptive) => time_passed
+                .saturating_sub(2 * adaptive.window.as_micros())
+                .max(self.timing.warmup.as_micros()),
+            None => self.timing.warmup.as_micros(),
+        };
+
+        let start = results.iter().find(|(_, t)| *t >= warmup);
+        match (start, results.last()) {
+            (Some((c_start, t_start)), Some((c_end, t_end))) if t_end > t_start => {
+                ((c_end - c_start) * 8) as f64 / (t_end - t_start) as f64
+            }
+            _ => 0.0,
+        }
+    }
 
-        ((c28 - c18) * 8) as f64 / (t28 - t18) as f64
+    /// Record why a worker gave up, the first reason recorded becomes the
+    /// status of the test.
+    pub fn fail(&self, status: &str) {
+        let mut f = self.failure.write().unwrap();
+        if f.is_none() {
+            *f = Some(status.to_string());
+        }
     }
 
     pub fn status(&self) -> String {
+        if let Some(status) = self.failure.read().unwrap().clone() {
+            return status;
+        }
+
+        // Why each connection failed is kept with the connections instead.
+        let slots = self.slots.read().unwrap();
+        if !slots.is_empty() && slots.iter().all(|s| s.unconnected.load(Ordering::Relaxed)) {
+            return String::from("失败");
+        }
+        drop(slots);
+
         let mut stop = 0;
-        let mut last = 0;
         let results = self.results.read().unwrap().to_vec();
+        let mut last = results.first().map(|(c, _)| *c).unwrap_or_default();
 
         #[cfg(debug_assertions)]
         debug!("Results {results:?}");
 
-        for (num, _) in results {
+        for (num, _) in results.into_iter().skip(1) {
             if num == last {
                 stop += 1;
             }
             last = num;
         }
 
-        if stop < 6 {
+        if self.timing.interval * stop < STALL_LIMIT {
             String::from("正常")
         } else {
             String::from("断流")
@@ -164,6 +773,55 @@ impl LoadCounter {
     }
 }
 
+/// What a worker thread or task counts with, without taking a lock shared
+/// with the other workers.
+pub struct LoadWorker {
+    counter: Arc<LoadCounter>,
+    slot: Arc<Slot>,
+}
+
+impl LoadWorker {
+    /// Called once after connecting or giving up, to start along with the
+    /// other workers.
+    pub fn wait(&self) {
+        self.counter.wait();
+    }
+
+    /// Called instead of `wait` when the worker could not connect. The test
+    /// fails only if no worker connected.
+    pub fn give_up(&self) {
+        self.slot.unconnected.store(true, Ordering::Relaxed);
+        self.counter.wait();
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.counter.is_end() || self.slot.stopped.load(Ordering::Relaxed)
+    }
+
+    pub fn increase(&self, count: u64) {
+        self.slot.bytes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record that the worker opened a new connection.
+    pub fn reconnect(&self) {
+        self.slot.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn fail(&self, status: &str) {
+        self.counter.fail(status);
+    }
+}
+
+/// A worker that returns before the test ended died early, including one
+/// that never connected.
+impl Drop for LoadWorker {
+    fn drop(&mut self) {
+        if !self.is_end() {
+            self.slot.died.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
 pub trait Client {
     fn result(&self) -> SpeedTestResult;
 
@@ -185,3 +843,96 @@ pub trait Client {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A counter that took the samples `results` with `timing`.
+    fn sampled(timing: LoadTiming, results: Vec<(u64, u128)>) -> LoadCounter {
+        LoadCounter {
+            timing,
+            results: RwLock::new(results),
+            ..LoadCounter::new(0)
+        }
+    }
+
+    fn adaptive() -> LoadTiming {
+        LoadTiming {
+            duration: Duration::from_secs(30),
+            warmup: Duration::ZERO,
+            interval: Duration::from_millis(500),
+            adaptive: Some(Adaptive {
+                tolerance: 0.1,
+                window: Duration::from_secs(2),
+            }),
+        }
+    }
+
+    /// Samples every half second up to `secs`, with `bytes(t)` bytes at `t`
+    /// microseconds. One byte per microsecond is 8 Mbps.
+    fn samples(secs: u64, bytes: impl Fn(u128) -> u64) -> Vec<(u64, u128)> {
+        (0..=secs * 2)
+            .map(|i| i as u128 * 500_000)
+            .map(|t| (bytes(t), t))
+            .collect()
+    }
+
+    #[test]
+    fn steady_series_is_stable() {
+        let timing = adaptive();
+        let counter = sampled(timing, samples(6, |t| t as u64));
+
+        assert!(counter.is_stable(&timing.adaptive.unwrap()));
+        assert_eq!(counter.speed(), 8.0);
+    }
+
+    #[test]
+    fn ramping_series_is_not_stable() {
+        let timing = adaptive();
+        let counter = sampled(timing, samples(6, |t| (t * t / 1_000_000) as u64));
+
+        assert!(!counter.is_stable(&timing.adaptive.unwrap()));
+    }
+
+    #[test]
+    fn too_few_samples() {
+        let timing = adaptive();
+        let counter = sampled(timing, samples(3, |t| t as u64));
+        assert!(!counter.is_stable(&timing.adaptive.unwrap()));
+
+        let counter = sampled(timing, samples(0, |t| t as u64));
+        assert_eq!(counter.speed(), 0.0);
+    }
+
+    #[test]
+    fn speed_leaves_out_warmup() {
+        let timing = LoadTiming {
+            warmup: Duration::from_secs(2),
+            ..Default::default()
+        };
+        // Twice as fast during the warm-up.
+        let counter = sampled(
+            timing,
+            samples(6, |t| match t {
+                0..=2_000_000 => 2 * t as u64,
+                _ => t as u64 + 2_000_000,
+            }),
+        );
+
+        assert_eq!(counter.speed(), 8.0);
+    }
+
+    #[test]
+    fn fails_only_when_no_worker_connected() {
+        let counter = Arc::new(sampled(LoadTiming::default(), samples(6, |t| t as u64)));
+        let _connected = counter.worker();
+        counter.worker().give_up();
+        assert_eq!(counter.status(), "正常");
+
+        let counter = Arc::new(sampled(LoadTiming::default(), samples(6, |_| 0)));
+        counter.worker().give_up();
+        counter.worker().give_up();
+        assert_eq!(counter.status(), "失败");
+    }
+}
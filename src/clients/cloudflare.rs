@@ -0,0 +1,294 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use url::Url;
+
+use crate::clients::base::{
+    get_address, host_port, make_connection, measure_latency, request_http_ping, AddressFamily,
+    Client, GenericStream,
+};
+use crate::clients::response::{read_body, read_response_head};
+use crate::utils::{median, AggregateSpeed, LatencyStats, SpeedTestResult};
+
+use std::io::Write;
+
+/// Request sizes in bytes and how many requests of each size are made, in
+/// the order the Cloudflare speed test runs them.
+const DOWNLOAD_RAMP: [(u64, u8); 6] = [
+    (100_000, 10),
+    (1_000_000, 8),
+    (10_000_000, 6),
+    (25_000_000, 4),
+    (100_000_000, 3),
+    (250_000_000, 2),
+];
+const UPLOAD_RAMP: [(u64, u8); 5] = [
+    (100_000, 8),
+    (1_000_000, 6),
+    (10_000_000, 4),
+    (25_000_000, 4),
+    (50_000_000, 3),
+];
+
+/// Requests faster than this are too short to say anything about bandwidth.
+const MIN_REQUEST_DURATION: Duration = Duration::from_millis(10);
+/// Once a request takes this long, larger sizes are skipped.
+const FINISH_REQUEST_DURATION: Duration = Duration::from_millis(1000);
+
+pub struct CloudflareClient {
+    download_url: Url,
+    upload_url: Url,
+
+    address: SocketAddr,
+
+    upload: f64,
+    upload_status: String,
+    upload_aggregate: f64,
+    download: f64,
+    download_status: String,
+    download_aggregate: f64,
+    latency: f64,
+    jitter: f64,
+    latency_stats: Option<LatencyStats>,
+}
+
+impl CloudflareClient {
+    pub fn build(url: String, family: AddressFamily) -> Option<Box<dyn Client>> {
+        let mut url = Url::parse(&url).ok()?;
+
+        // `__down` and `__up` sit in the directory given, with or without a
+        // trailing slash.
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+
+        let download_url = url.join("__down").ok()?;
+        let upload_url = url.join("__up").ok()?;
+
+        let address = get_address(&url, family)?;
+
+        #[cfg(debug_assertions)]
+        debug!("IP address {address}");
+
+        let r = "取消".to_owned();
+        Some(Box::new(Self {
+            download_url,
+            upload_url,
+            address,
+            upload: 0.0,
+            upload_status: r.clone(),
+            upload_aggregate: 0.0,
+            download: 0.0,
+            download_status: r,
+            download_aggregate: 0.0,
+            latency: 0.0,
+            jitter: 0.0,
+            latency_stats: None,
+        }))
+    }
+
+    /// Run the requests of `ramp` one after another on a keep-alive
+    /// connection, stopping after the first size that takes long enough or
+    /// once the server can not be reached again.
+    fn run_ramp(&mut self, load: u8) -> bool {
+        let (url, ramp) = match load {
+            0 => (&self.upload_url, &UPLOAD_RAMP[..]),
+            _ => (&self.download_url, &DOWNLOAD_RAMP[..]),
+        };
+        let rtt = Duration::from_micros((self.latency * 1_000.0) as u64);
+
+        let mut stream = make_connection(&self.address, url).ok();
+        let mut pending = vec![];
+        let mut speeds = vec![];
+        let mut total_bytes = 0;
+        let mut total_time = Duration::ZERO;
+        let mut failed = false;
+
+        'ramp: for &(bytes, count) in ramp {
+            let mut finish = false;
+
+            for _ in 0..count {
+                let s = match stream.as_mut() {
+                    Some(s) => s,
+                    None => break 'ramp,
+                };
+                let r = match load {
+                    0 => request_cloudflare_upload(s, &mut pending, url, bytes, rtt),
+                    _ => request_cloudflare_download(s, &mut pending, url, bytes),
+                };
+
+                match r {
+                    Ok(used) => {
+                        #[cfg(debug_assertions)]
+                        debug!("Request {bytes} bytes in {used:?}");
+
+                        total_bytes += bytes;
+                        total_time += used;
+                        if used >= MIN_REQUEST_DURATION {
+                            speeds.push((bytes * 8) as f64 / used.as_micros() as f64);
+                        }
+                        if used >= FINISH_REQUEST_DURATION {
+                            finish = true;
+                        }
+                    }
+                    Err(_e) => {
+                        #[cfg(debug_assertions)]
+                        debug!("Request {bytes} bytes Error: {_e}");
+
+                        failed = true;
+                        stream = make_connection(&self.address, url).ok();
+                        pending.clear();
+                    }
+                }
+            }
+
+            if finish {
+                break;
+            }
+        }
+
+        let status = match (speeds.is_empty(), failed) {
+            (true, _) => "失败",
+            (false, true) => "断流",
+            (false, false) => "正常",
+        };
+        let speed = median(&speeds);
+        let aggregate = match total_time.as_micros() {
+            0 => 0.0,
+            t => (total_bytes * 8) as f64 / t as f64,
+        };
+
+        match load {
+            0 => {
+                self.upload = speed;
+                self.upload_status = status.to_string();
+                self.upload_aggregate = aggregate;
+            }
+            _ => {
+                self.download = speed;
+                self.download_status = status.to_string();
+                self.download_aggregate = aggregate;
+            }
+        }
+
+        !speeds.is_empty()
+    }
+}
+
+impl Client for CloudflareClient {
+    fn ping(&mut self) -> bool {
+        let stats = measure_latency(&self.address, &self.download_url, |_| {
+            request_http_ping(&self.address, &self.download_url, "bytes=0")
+        });
+
+        self.latency = stats.min;
+        self.jitter = stats.jitter;
+
+        let ok = stats.failed < stats.samples;
+        self.latency_stats = Some(stats);
+        ok
+    }
+
+    fn download(&mut self) -> bool {
+        self.run_ramp(1)
+    }
+
+    fn upload(&mut self) -> bool {
+        self.run_ramp(0)
+    }
+
+    fn result(&self) -> SpeedTestResult {
+        let mut r = SpeedTestResult::build(
+            self.upload,
+            self.upload_status.clone(),
+            self.download,
+            self.download_status.clone(),
+            self.latency,
+            self.jitter,
+        );
+        r.set_aggregate(AggregateSpeed {
+            upload: self.upload_aggregate,
+            download: self.download_aggregate,
+        });
+        if let Some(stats) = &self.latency_stats {
+            r.set_latency_stats(stats.clone());
+        }
+        r
+    }
+}
+
+/// Download `bytes` bytes, returning the time from the first to the last
+/// response byte so the request round trip is left out.
+fn request_cloudflare_download(
+    stream: &mut Box<dyn GenericStream>,
+    pending: &mut Vec<u8>,
+    url: &Url,
+    bytes: u64,
+) -> Result<Duration, String> {
+    let request_head = format!(
+        "GET {}?bytes={} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bim/1.0\r\n\r\n",
+        url.path(),
+        bytes,
+        host_port(url),
+    )
+    .into_bytes();
+    stream.write_all(&request_head).map_err(|e| e.to_string())?;
+
+    let head = read_response_head(stream, pending)?;
+    if head.failure_status().is_some() {
+        return Err(format!("{} {}", head.status, head.reason));
+    }
+
+    let now = Instant::now();
+    read_body(stream, pending, head.body(), |_| true)?;
+
+    Ok(now.elapsed())
+}
+
+/// Upload `bytes` bytes, returning the time until the response arrives less
+/// one round trip.
+fn request_cloudflare_upload(
+    stream: &mut Box<dyn GenericStream>,
+    pending: &mut Vec<u8>,
+    url: &Url,
+    bytes: u64,
+    rtt: Duration,
+) -> Result<Duration, String> {
+    let mut data_counter = 0;
+
+    let request_chunk = "0123456789AaBbCcDdEeFfGgHhIiJjKkLlMmNnOoPpQqRrSsTtUuVvWwXxYyZz-="
+        .repeat(1024)
+        .into_bytes();
+
+    let request_head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bim/1.0\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        url.path(),
+        host_port(url),
+        bytes
+    )
+    .into_bytes();
+    stream.write_all(&request_head).map_err(|e| e.to_string())?;
+
+    let now = Instant::now();
+    while data_counter < bytes {
+        let left = (bytes - data_counter).min(request_chunk.len() as u64) as usize;
+        match stream.write(&request_chunk[..left]) {
+            Ok(size) => data_counter += size as u64,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    let head = read_response_head(stream, pending)?;
+    let used = now.elapsed().saturating_sub(rtt);
+    if head.failure_status().is_some() {
+        return Err(format!("{} {}", head.status, head.reason));
+    }
+
+    read_body(stream, pending, head.body(), |_| true)?;
+
+    Ok(used)
+}
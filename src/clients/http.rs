@@ -2,45 +2,65 @@ use std::error::Error;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
 
 #[cfg(debug_assertions)]
 use log::debug;
 
-use url::Url;
+use url::{Position, Url};
 
-use crate::clients::base::{get_address, make_connection, request_tcp_ping, Client, LoadCounter};
-use crate::utils::SpeedTestResult;
+use crate::clients::base::{
+    get_address, make_connection, make_probe_connection, measure_latency, request_tcp_ping,
+    AddressFamily, Client, LoadCounter, LoadWorker,
+};
+use crate::clients::response::{read_body, read_response_head, response_error};
+use crate::utils::{FlowReport, LatencyStats, Saturation, SpeedTestResult, TestDuration};
 
-use std::io::{Read, Write};
+use std::io::Write;
 use std::time::SystemTime;
 
 pub struct HTTPClient {
     download_url: Url,
     upload_url: Url,
     threads: u8,
+    file: bool,
 
     address: SocketAddr,
+    upload_address: SocketAddr,
+    upload_redirects_exceeded: bool,
+    download_redirects_exceeded: bool,
 
     upload: f64,
     upload_status: String,
     download: f64,
     download_status: String,
+    duration: TestDuration,
+    upload_flows: FlowReport,
+    download_flows: FlowReport,
+    saturation: Option<Saturation>,
     latency: f64,
     jitter: f64,
+    latency_stats: Option<LatencyStats>,
 }
 
 impl HTTPClient {
     pub fn build(
         download_url: String,
         upload_url: String,
-        ipv6: bool,
+        family: AddressFamily,
         threads: u8,
+        file: bool,
+        max_redirects: u8,
     ) -> Option<Box<dyn Client>> {
         let download_url = Url::parse(&download_url).ok()?;
         let upload_url = Url::parse(&upload_url).ok()?;
 
-        let address = get_address(&download_url, ipv6)?;
+        let address = get_address(&download_url, family)?;
+        let upload_address = get_address(&upload_url, family)?;
+
+        let (upload_url, upload_address, upload_redirects_exceeded) =
+            follow_redirects(upload_url, upload_address, family, "POST", max_redirects);
+        let (download_url, address, download_redirects_exceeded) =
+            follow_redirects(download_url, address, family, "GET", max_redirects);
 
         #[cfg(debug_assertions)]
         debug!("IP address {address}");
@@ -50,49 +70,64 @@ impl HTTPClient {
             download_url,
             upload_url,
             threads,
+            file,
             address,
+            upload_address,
+            upload_redirects_exceeded,
+            download_redirects_exceeded,
             upload: 0.0,
             upload_status: r.clone(),
             download: 0.0,
             download_status: r.clone(),
+            duration: TestDuration::default(),
+            upload_flows: FlowReport::default(),
+            download_flows: FlowReport::default(),
+            saturation: None,
             latency: 0.0,
             jitter: 0.0,
+            latency_stats: None,
         }))
     }
 
     fn run_load(&mut self, load: u8) -> Result<bool, Box<dyn Error>> {
-        let url = match load {
-            0 => self.upload_url.clone(),
-            _ => self.download_url.clone(),
+        let (url, address, redirects_exceeded) = match load {
+            0 => (
+                self.upload_url.clone(),
+                self.upload_address,
+                self.upload_redirects_exceeded,
+            ),
+            _ => (
+                self.download_url.clone(),
+                self.address,
+                self.download_redirects_exceeded,
+            ),
         };
-        let counter = Arc::new(LoadCounter::new(self.threads));
-        let mut tasks = vec![];
 
-        for _ in 0..self.threads {
-            let a = self.address.clone();
+        // The URL still redirects after `-r` hops, so there is nothing to load.
+        if redirects_exceeded {
+            match load {
+                0 => self.upload_status = "跳转".to_owned(),
+                _ => self.download_status = "跳转".to_owned(),
+            }
+            return Ok(true);
+        }
+
+        let counter = Arc::new(LoadCounter::with_ramp_up(self.threads));
+        let tasks = counter.start(self.threads, |c| {
+            let a = address;
             let u = url.clone();
-            let c = counter.clone();
+            let file = self.file;
 
-            let task = thread::spawn(move || {
-                match load {
-                    0 => request_http_upload(a, u, c),
+            thread::spawn(move || {
+                match (load, file) {
+                    (0, _) => request_http_upload(a, u, c),
+                    (_, true) => request_http_file_download(a, u, c),
                     _ => request_http_download(a, u, c),
                 };
-            });
-            tasks.push(task);
-            thread::sleep(Duration::from_millis(250));
-        }
+            })
+        });
 
-        let mut time_passed = 0;
-        counter.wait();
-
-        let now = Instant::now();
-        while time_passed < 14_000_000 {
-            thread::sleep(Duration::from_millis(500));
-            time_passed = now.elapsed().as_micros();
-
-            counter.count(time_passed);
-        }
+        let time_passed = counter.measure();
 
         counter.end();
         for task in tasks {
@@ -103,10 +138,24 @@ impl HTTPClient {
             0 => {
                 self.upload = counter.speed();
                 self.upload_status = counter.status();
+                self.duration.upload = time_passed as f64 / 1_000_000.0;
+                self.upload_flows = counter.flows();
+                if let Some(n) = counter.saturation() {
+                    self.saturation
+                        .get_or_insert_with(Saturation::default)
+                        .upload = n;
+                }
             }
             _ => {
                 self.download = counter.speed();
                 self.download_status = counter.status();
+                self.duration.download = time_passed as f64 / 1_000_000.0;
+                self.download_flows = counter.flows();
+                if let Some(n) = counter.saturation() {
+                    self.saturation
+                        .get_or_insert_with(Saturation::default)
+                        .download = n;
+                }
             }
         }
 
@@ -116,78 +165,122 @@ impl HTTPClient {
 
 impl Client for HTTPClient {
     fn ping(&mut self) -> bool {
-        let mut count = 0;
-        let mut pings = [0u128; 6];
-        let mut ping_min = 10000000;
-
-        while count < 6 {
-            let ping = request_tcp_ping(&self.address);
-            if ping > 0 {
-                if ping < ping_min {
-                    ping_min = ping
-                }
-                pings[count] = ping;
-            }
-            thread::sleep(Duration::from_millis(1000));
-            count += 1;
-        }
-
-        if pings == [0, 0, 0, 0, 0, 0] {
-            self.latency = 0.0;
-            self.jitter = 0.0;
-            return false;
-        }
-
-        let mut jitter_all = 0;
-        for p in pings {
-            if p > 0 {
-                jitter_all += p - ping_min;
-            }
-        }
+        let stats = measure_latency(&self.address, &self.download_url, |_| {
+            request_tcp_ping(&self.address, &self.download_url)
+        });
 
-        self.latency = ping_min as f64 / 1_000.0;
-        self.jitter = jitter_all as f64 / 5_000.0;
+        self.latency = stats.min;
+        self.jitter = stats.jitter;
 
-        #[cfg(debug_assertions)]
-        debug!("Ping {} ms", self.latency);
-
-        #[cfg(debug_assertions)]
-        debug!("Jitter {} ms", self.jitter);
-
-        true
+        let ok = stats.failed < stats.samples;
+        self.latency_stats = Some(stats);
+        ok
     }
 
     fn download(&mut self) -> bool {
-        match self.run_load(1) {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+        self.run_load(1).is_ok()
     }
 
     fn upload(&mut self) -> bool {
-        match self.run_load(0) {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+        self.run_load(0).is_ok()
     }
 
     fn result(&self) -> SpeedTestResult {
-        SpeedTestResult::build(
+        let mut r = SpeedTestResult::build(
             self.upload,
             self.upload_status.clone(),
             self.download,
             self.download_status.clone(),
             self.latency,
             self.jitter,
+        );
+        r.set_urls(self.upload_url.to_string(), self.download_url.to_string());
+        r.set_duration(self.duration.clone());
+        r.set_flows(self.upload_flows.clone(), self.download_flows.clone());
+        if let Some(saturation) = &self.saturation {
+            r.set_saturation(saturation.clone());
+        }
+        if let Some(stats) = &self.latency_stats {
+            r.set_latency_stats(stats.clone());
+        }
+        r
+    }
+}
+
+/// Follow up to `max_redirects` redirects of a `method` request to `url`,
+/// returning the URL to measure, its address and whether it still redirects
+/// after the last hop. A `GET` is probed with `HEAD` so no body is sent.
+/// Anything unexpected stops the chain, so the timed phase reports it
+/// instead. The probes are left out of the connections in the result.
+fn follow_redirects(
+    mut url: Url,
+    mut address: SocketAddr,
+    family: AddressFamily,
+    method: &str,
+    max_redirects: u8,
+) -> (Url, SocketAddr, bool) {
+    let method = match method {
+        "GET" => "HEAD",
+        m => m,
+    };
+
+    for hop in 0..=max_redirects {
+        let mut stream = match make_probe_connection(&address, &url) {
+            Ok(s) => s,
+            Err(_) => break,
+        };
+
+        let request_head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: bim/1.0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            method,
+            &url[Position::BeforePath..Position::AfterQuery],
+            url.host_str().unwrap(),
+            url.port_or_known_default().unwrap()
         )
+        .into_bytes();
+        if stream.write_all(&request_head).is_err() {
+            break;
+        }
+
+        let head = match read_response_head(&mut stream, &mut vec![]) {
+            Ok(head) => head,
+            Err(_) => break,
+        };
+        drop(stream);
+
+        if !matches!(head.status, 301 | 302 | 307 | 308) {
+            break;
+        }
+
+        if hop == max_redirects {
+            #[cfg(debug_assertions)]
+            debug!("Redirect limit {max_redirects} reached at {url}");
+
+            return (url, address, true);
+        }
+
+        let next = match head.header("location").and_then(|l| url.join(l).ok()) {
+            Some(u) => u,
+            None => break,
+        };
+        let next_address = match get_address(&next, family) {
+            Some(a) => a,
+            None => break,
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Redirect {} {url} -> {next}", head.status);
+
+        url = next;
+        address = next_address;
     }
+
+    (url, address, false)
 }
 
-fn request_http_download(address: SocketAddr, url: Url, counter: Arc<LoadCounter>) {
+fn request_http_download(address: SocketAddr, url: Url, counter: LoadWorker) {
     let chunk_count = 50;
-    let data_size = chunk_count * 1024 * 1024 as u64;
-    let mut data_counter;
-    let mut buffer = [0; 65536];
+    let data_size = chunk_count * 1024 * 1024_u64;
 
     let host_port = format!(
         "{}:{}",
@@ -199,14 +292,15 @@ fn request_http_download(address: SocketAddr, url: Url, counter: Arc<LoadCounter
     let mut stream = match make_connection(&address, &url) {
         Ok(s) => s,
         Err(_) => {
-            counter.wait();
+            counter.give_up();
             return;
         }
     };
 
+    let mut pending = vec![];
     counter.wait();
 
-    'request: while !counter.is_end() {
+    while !counter.is_end() {
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
@@ -225,52 +319,159 @@ fn request_http_download(address: SocketAddr, url: Url, counter: Arc<LoadCounter
         )
         .into_bytes();
 
-        match stream.write_all(&request_head) {
-            Ok(_) => {
-                if let Ok(size) = stream.read(&mut buffer) {
-                    #[cfg(debug_assertions)]
-                    debug!("Download Status: {size}");
-
-                    if size > 0 {
-                        let count = size as u64;
-                        data_counter = count;
-                        counter.increase(count);
-                    } else {
-                        break 'request;
-                    }
-                } else {
-                    break 'request;
+        if let Err(_e) = stream.write_all(&request_head) {
+            #[cfg(debug_assertions)]
+            debug!("Download Error: {}", _e);
+
+            return;
+        }
+
+        let head = match read_response_head(&mut stream, &mut pending) {
+            Ok(r) => r,
+            Err(e) => return response_error(&counter, e),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Download Status: {} {}", head.status, head.reason);
+
+        if let Some(status) = head.failure_status() {
+            counter.fail(status);
+            return;
+        }
+
+        let r = read_body(&mut stream, &mut pending, head.body(), |size| {
+            counter.increase(size);
+            !counter.is_end()
+        });
+        match r {
+            Ok(true) if head.keep_alive() => continue,
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => return response_error(&counter, e),
+        }
+
+        counter.reconnect();
+        pending.clear();
+        stream = match make_connection(&address, &url) {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+    }
+}
+
+/// Download `url` as is until the test ends. A file shorter than the test
+/// is fetched again with a `Range` request for its whole length, and a
+/// response cut short is resumed on a new connection from where it stopped.
+fn request_http_file_download(address: SocketAddr, url: Url, counter: LoadWorker) {
+    let mut offset = 0;
+    let mut length: Option<u64> = None;
+
+    let host_port = format!(
+        "{}:{}",
+        url.host_str().unwrap(),
+        url.port_or_known_default().unwrap()
+    );
+    let path_query = &url[Position::BeforePath..Position::AfterQuery];
+
+    let mut stream = match make_connection(&address, &url) {
+        Ok(s) => s,
+        Err(_) => {
+            counter.give_up();
+            return;
+        }
+    };
+
+    let mut pending = vec![];
+    counter.wait();
+
+    while !counter.is_end() {
+        let range = match (offset, length) {
+            (0, None) => String::new(),
+            (_, None) => format!("Range: bytes={offset}-\r\n"),
+            (_, Some(n)) => format!("Range: bytes={offset}-{}\r\n", n - 1),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Download {path_query} from {offset}");
+
+        let request_head = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bim/1.0\r\n{}\r\n",
+            path_query, host_port, range
+        )
+        .into_bytes();
+
+        if let Err(_e) = stream.write_all(&request_head) {
+            #[cfg(debug_assertions)]
+            debug!("Download Error: {}", _e);
+
+            return;
+        }
+
+        let head = match read_response_head(&mut stream, &mut pending) {
+            Ok(r) => r,
+            Err(e) => return response_error(&counter, e),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Download Status: {} {}", head.status, head.reason);
+
+        if let Some(status) = head.failure_status() {
+            counter.fail(status);
+            return;
+        }
+        if head.status != 206 {
+            offset = 0;
+        }
+
+        let mut received = 0;
+        let r = read_body(&mut stream, &mut pending, head.body(), |size| {
+            received += size;
+            offset += size;
+            counter.increase(size);
+            !counter.is_end()
+        });
+        match r {
+            // Asking again for an empty file would only spin.
+            Ok(true) if received == 0 => {
+                #[cfg(debug_assertions)]
+                debug!("Download Error: empty body");
+
+                counter.fail("失败");
+                return;
+            }
+            Ok(true) => {
+                if offset > 0 {
+                    length = Some(offset);
+                }
+                offset = 0;
+                if head.keep_alive() {
+                    continue;
                 }
             }
+            Ok(false) => return,
+            Err(e) if e.status().is_some() => return response_error(&counter, e),
             Err(_e) => {
                 #[cfg(debug_assertions)]
                 debug!("Download Error: {}", _e);
-
-                break 'request;
             }
         }
 
-        while data_counter < data_size && !counter.is_end() {
-            match stream.read(&mut buffer) {
-                Ok(size) => {
-                    let count = size as u64;
-                    data_counter += count;
-                    counter.increase(count);
-                }
-                Err(_e) => {
-                    #[cfg(debug_assertions)]
-                    debug!("Download Error: {}", _e);
-
-                    break 'request;
-                }
-            }
+        if counter.is_end() {
+            return;
         }
+
+        counter.reconnect();
+        pending.clear();
+        stream = match make_connection(&address, &url) {
+            Ok(s) => s,
+            Err(_) => return,
+        };
     }
 }
 
-fn request_http_upload(address: SocketAddr, url: Url, counter: Arc<LoadCounter>) {
+fn request_http_upload(address: SocketAddr, url: Url, counter: LoadWorker) {
     let chunk_count = 50;
-    let data_size = chunk_count * 1024 * 1024 as u64;
+    let data_size = chunk_count * 1024 * 1024_u64;
     let mut data_counter;
 
     let host_port = format!(
@@ -286,14 +487,15 @@ fn request_http_upload(address: SocketAddr, url: Url, counter: Arc<LoadCounter>)
     let mut stream = match make_connection(&address, &url) {
         Ok(s) => s,
         Err(_) => {
-            counter.wait();
+            counter.give_up();
             return;
         }
     };
 
+    let mut pending = vec![];
     counter.wait();
 
-    'request: while !counter.is_end() {
+    while !counter.is_end() {
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
@@ -311,32 +513,65 @@ fn request_http_upload(address: SocketAddr, url: Url, counter: Arc<LoadCounter>)
 
         match stream.write_all(&request_head) {
             Ok(_) => {
-                let length = request_head.len() as u64;
-                data_counter = length;
-                counter.increase(length);
+                data_counter = 0;
+                counter.increase(request_head.len() as u64);
             }
             Err(_e) => {
                 #[cfg(debug_assertions)]
                 debug!("Upload Error: {}", _e);
 
-                break 'request;
+                return;
             }
         }
 
         while data_counter < data_size && !counter.is_end() {
-            match stream.write(&request_chunk) {
+            let left = (data_size - data_counter).min(request_chunk.len() as u64) as usize;
+            match stream.write(&request_chunk[..left]) {
                 Ok(size) => {
                     let count = size as u64;
-                    data_counter += size as u64;
+                    data_counter += count;
                     counter.increase(count);
                 }
                 Err(_e) => {
                     #[cfg(debug_assertions)]
                     debug!("Upload Error: {}", _e);
 
-                    break 'request;
+                    return;
                 }
             }
         }
+
+        if counter.is_end() {
+            return;
+        }
+
+        let head = match read_response_head(&mut stream, &mut pending) {
+            Ok(r) => r,
+            Err(e) => return response_error(&counter, e),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Upload Status: {} {}", head.status, head.reason);
+
+        if let Some(status) = head.failure_status() {
+            counter.fail(status);
+            return;
+        }
+
+        match read_body(&mut stream, &mut pending, head.body(), |_| {
+            !counter.is_end()
+        }) {
+            Ok(true) if head.keep_alive() => continue,
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => return response_error(&counter, e),
+        }
+
+        counter.reconnect();
+        pending.clear();
+        stream = match make_connection(&address, &url) {
+            Ok(s) => s,
+            Err(_) => return,
+        };
     }
 }
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use hpack::{Decoder, Encoder};
+use url::Url;
+
+use crate::clients::base::{
+    get_address, make_alpn_connection, measure_latency, request_tcp_ping, AddressFamily, Client,
+    GenericStream, LoadCounter, LoadWorker,
+};
+use crate::clients::response::status_failure;
+use crate::utils::{FlowReport, LatencyStats, SpeedTestResult, TestDuration};
+
+use std::time::SystemTime;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_PING: u8 = 0x6;
+const FRAME_GOAWAY: u8 = 0x7;
+const FRAME_WINDOW_UPDATE: u8 = 0x8;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+const WINDOW_SIZE: u32 = 16 * 1024 * 1024;
+const BODY_SIZE: u64 = 50 * 1024 * 1024;
+
+pub struct HTTP2Client {
+    download_url: Url,
+    upload_url: Url,
+    streams: u8,
+
+    address: SocketAddr,
+    upload_address: SocketAddr,
+
+    upload: f64,
+    upload_status: String,
+    download: f64,
+    download_status: String,
+    duration: TestDuration,
+    upload_flows: FlowReport,
+    download_flows: FlowReport,
+    latency: f64,
+    jitter: f64,
+    latency_stats: Option<LatencyStats>,
+}
+
+impl HTTP2Client {
+    pub fn build(
+        download_url: String,
+        upload_url: String,
+        family: AddressFamily,
+        streams: u8,
+    ) -> Option<Box<dyn Client>> {
+        let download_url = Url::parse(&download_url).ok()?;
+        let upload_url = Url::parse(&upload_url).ok()?;
+
+        let address = get_address(&download_url, family)?;
+        let upload_address = get_address(&upload_url, family)?;
+
+        #[cfg(debug_assertions)]
+        debug!("IP address {address}");
+
+        let r = "取消".to_owned();
+        Some(Box::new(Self {
+            download_url,
+            upload_url,
+            streams,
+            address,
+            upload_address,
+            upload: 0.0,
+            upload_status: r.clone(),
+            download: 0.0,
+            download_status: r,
+            duration: TestDuration::default(),
+            upload_flows: FlowReport::default(),
+            download_flows: FlowReport::default(),
+            latency: 0.0,
+            jitter: 0.0,
+            latency_stats: None,
+        }))
+    }
+
+    fn run_load(&mut self, load: u8) -> Result<bool, Box<dyn Error>> {
+        let (url, a) = match load {
+            0 => (self.upload_url.clone(), self.upload_address),
+            _ => (self.download_url.clone(), self.address),
+        };
+        let counter = Arc::new(LoadCounter::new(1));
+
+        let s = self.streams;
+        let c = counter.worker();
+        let task = thread::spawn(move || request_h2_load(a, url, s, load == 0, c));
+
+        counter.wait();
+
+        let time_passed = counter.measure();
+
+        counter.end();
+        let _ = task.join();
+
+        match load {
+            0 => {
+                self.upload = counter.speed();
+                self.upload_status = counter.status();
+                self.duration.upload = time_passed as f64 / 1_000_000.0;
+                self.upload_flows = counter.flows();
+            }
+            _ => {
+                self.download = counter.speed();
+                self.download_status = counter.status();
+                self.duration.download = time_passed as f64 / 1_000_000.0;
+                self.download_flows = counter.flows();
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Client for HTTP2Client {
+    fn ping(&mut self) -> bool {
+        let stats = measure_latency(&self.address, &self.download_url, |_| {
+            request_tcp_ping(&self.address, &self.download_url)
+        });
+
+        self.latency = stats.min;
+        self.jitter = stats.jitter;
+
+        let ok = stats.failed < stats.samples;
+        self.latency_stats = Some(stats);
+        ok
+    }
+
+    fn download(&mut self) -> bool {
+        self.run_load(1).is_ok()
+    }
+
+    fn upload(&mut self) -> bool {
+        self.run_load(0).is_ok()
+    }
+
+    fn result(&self) -> SpeedTestResult {
+        let mut r = SpeedTestResult::build(
+            self.upload,
+            self.upload_status.clone(),
+            self.download,
+            self.download_status.clone(),
+            self.latency,
+            self.jitter,
+        );
+        r.set_duration(self.duration.clone());
+        r.set_flows(self.upload_flows.clone(), self.download_flows.clone());
+        if let Some(stats) = &self.latency_stats {
+            r.set_latency_stats(stats.clone());
+        }
+        r
+    }
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    id: u32,
+    payload: Vec<u8>,
+}
+
+struct H2Stream {
+    remaining: u64,
+    window: i64,
+    unacked: u32,
+}
+
+/// A minimal HTTP/2 client connection: enough framing, flow control and
+/// HPACK state to keep several request streams busy on one socket.
+struct H2Connection {
+    stream: Box<dyn GenericStream>,
+    encoder: Encoder<'static>,
+    decoder: Decoder<'static>,
+    next_id: u32,
+    last_sent: u32,
+    window: i64,
+    unacked: u32,
+    initial_window: i64,
+    max_streams: usize,
+    max_frame: usize,
+    streams: HashMap<u32, H2Stream>,
+    header_block: Vec<u8>,
+    /// Status for the test once a response was not a success.
+    failure: Option<&'static str>,
+}
+
+impl H2Connection {
+    fn handshake(stream: Box<dyn GenericStream>) -> Result<Self, Box<dyn Error>> {
+        let mut conn = Self {
+            stream,
+            encoder: Encoder::new(),
+            decoder: Decoder::new(),
+            next_id: 1,
+            last_sent: 0,
+            window: DEFAULT_WINDOW_SIZE,
+            unacked: 0,
+            initial_window: DEFAULT_WINDOW_SIZE,
+            max_streams: usize::MAX,
+            max_frame: 16384,
+            streams: HashMap::new(),
+            header_block: vec![],
+            failure: None,
+        };
+
+        let mut settings = vec![];
+        for (id, value) in [
+            (SETTINGS_ENABLE_PUSH, 0),
+            (SETTINGS_INITIAL_WINDOW_SIZE, WINDOW_SIZE),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+
+        conn.stream.write_all(PREFACE)?;
+        conn.write_frame(FRAME_SETTINGS, 0, 0, &settings)?;
+        conn.write_frame(
+            FRAME_WINDOW_UPDATE,
+            0,
+            0,
+            &(WINDOW_SIZE - DEFAULT_WINDOW_SIZE as u32).to_be_bytes(),
+        )?;
+
+        let frame = conn.read_frame()?;
+        if frame.kind != FRAME_SETTINGS {
+            return Err("server does not speak HTTP/2".into());
+        }
+        conn.handle_frame(frame, None)?;
+
+        Ok(conn)
+    }
+
+    fn write_frame(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        id: u32,
+        payload: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut head = [0; 9];
+        head[0..3].copy_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        head[3] = kind;
+        head[4] = flags;
+        head[5..9].copy_from_slice(&id.to_be_bytes());
+
+        self.stream.write_all(&head)?;
+        self.stream.write_all(payload)?;
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<Frame, Box<dyn Error>> {
+        let mut head = [0; 9];
+        self.stream.read_exact(&mut head)?;
+
+        let size = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        let id = u32::from_be_bytes(head[5..9].try_into().unwrap()) & 0x7fff_ffff;
+        let mut payload = vec![0; size];
+        self.stream.read_exact(&mut payload)?;
+
+        Ok(Frame {
+            kind: head[3],
+            flags: head[4],
+            id,
+            payload,
+        })
+    }
+
+    fn open_stream(&mut self, url: &Url, upload: bool) -> Result<(), Box<dyn Error>> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let path_query = match upload {
+            true => format!("{}?r={}", url.path(), now),
+            false => format!(
+                "{}?cors=true&r={}&ckSize=50&size={}",
+                url.path(),
+                now,
+                BODY_SIZE
+            ),
+        };
+        let authority = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap(), port),
+            None => url.host_str().unwrap().to_string(),
+        };
+        let length = BODY_SIZE.to_string();
+
+        let mut headers: Vec<(&[u8], &[u8])> = vec![
+            (b":method", if upload { &b"POST"[..] } else { &b"GET"[..] }),
+            (b":scheme", url.scheme().as_bytes()),
+            (b":authority", authority.as_bytes()),
+            (b":path", path_query.as_bytes()),
+            (b"user-agent", b"bim/1.0"),
+        ];
+        if upload {
+            headers.push((b"content-length", length.as_bytes()));
+        }
+        let block = self.encoder.encode(headers);
+
+        #[cfg(debug_assertions)]
+        debug!("HTTP/2 stream {} {path_query}", self.next_id);
+
+        let id = self.next_id;
+        let flags = match upload {
+            true => FLAG_END_HEADERS,
+            false => FLAG_END_HEADERS | FLAG_END_STREAM,
+        };
+        self.write_frame(FRAME_HEADERS, flags, id, &block)?;
+
+        self.streams.insert(
+            id,
+            H2Stream {
+                remaining: if upload { BODY_SIZE } else { 0 },
+                window: self.initial_window,
+                unacked: 0,
+            },
+        );
+        self.next_id += 2;
+        Ok(())
+    }
+
+    /// Next stream in id order after the last one written that still has
+    /// body left and flow control window to send it.
+    fn sendable_stream(&self) -> Option<u32> {
+        if self.window <= 0 {
+            return None;
+        }
+
+        let mut ids: Vec<u32> = self
+            .streams
+            .iter()
+            .filter(|(_, s)| s.remaining > 0 && s.window > 0)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+
+        ids.iter()
+            .find(|id| **id > self.last_sent)
+            .or(ids.first())
+            .copied()
+    }
+
+    fn send_data(&mut self, id: u32, chunk: &[u8]) -> Result<usize, Box<dyn Error>> {
+        let s = self.streams.get(&id).unwrap();
+        let size = (chunk.len() as u64)
+            .min(self.max_frame as u64)
+            .min(s.remaining)
+            .min(s.window as u64)
+            .min(self.window as u64) as usize;
+        let flags = match s.remaining == size as u64 {
+            true => FLAG_END_STREAM,
+            false => 0,
+        };
+
+        self.write_frame(FRAME_DATA, flags, id, &chunk[..size])?;
+
+        let s = self.streams.get_mut(&id).unwrap();
+        s.remaining -= size as u64;
+        s.window -= size as i64;
+        self.window -= size as i64;
+        self.last_sent = id;
+        Ok(size)
+    }
+
+    fn handle_frame(
+        &mut self,
+        frame: Frame,
+        counter: Option<&LoadWorker>,
+    ) -> Result<(), Box<dyn Error>> {
+        let Frame {
+            kind,
+            flags,
+            id,
+            payload,
+        } = frame;
+
+        match kind {
+            FRAME_DATA => {
+                let size = payload.len() as u32;
+                let data = strip_padding(flags, &payload)?;
+                if let Some(c) = counter {
+                    c.increase(data.len() as u64);
+                }
+
+                self.unacked += size;
+                if self.unacked > WINDOW_SIZE / 2 {
+                    let increment = self.unacked;
+                    self.unacked = 0;
+                    self.write_frame(FRAME_WINDOW_UPDATE, 0, 0, &increment.to_be_bytes())?;
+                }
+
+                let mut increment = 0;
+                if let Some(s) = self.streams.get_mut(&id) {
+                    s.unacked += size;
+                    if s.unacked > WINDOW_SIZE / 2 && flags & FLAG_END_STREAM == 0 {
+                        increment = s.unacked;
+                        s.unacked = 0;
+                    }
+                }
+                if increment > 0 {
+                    self.write_frame(FRAME_WINDOW_UPDATE, 0, id, &increment.to_be_bytes())?;
+                }
+            }
+            FRAME_HEADERS => {
+                let mut block = strip_padding(flags, &payload)?;
+                if flags & FLAG_PRIORITY != 0 {
+                    block = block.get(5..).ok_or("bad HEADERS frame")?;
+                }
+                self.header_block = block.to_vec();
+                if flags & FLAG_END_HEADERS != 0 {
+                    self.decode_headers(id)?;
+                }
+            }
+            FRAME_CONTINUATION => {
+                self.header_block.extend_from_slice(&payload);
+                if flags & FLAG_END_HEADERS != 0 {
+                    self.decode_headers(id)?;
+                }
+            }
+            FRAME_RST_STREAM => {
+                self.streams.remove(&id);
+            }
+            FRAME_SETTINGS if flags & FLAG_ACK == 0 => {
+                for setting in payload.chunks_exact(6) {
+                    let value = u32::from_be_bytes(setting[2..6].try_into().unwrap());
+                    match u16::from_be_bytes([setting[0], setting[1]]) {
+                        SETTINGS_MAX_CONCURRENT_STREAMS => self.max_streams = value as usize,
+                        SETTINGS_INITIAL_WINDOW_SIZE => {
+                            let delta = value as i64 - self.initial_window;
+                            self.initial_window = value as i64;
+                            for s in self.streams.values_mut() {
+                                s.window += delta;
+                            }
+                        }
+                        SETTINGS_MAX_FRAME_SIZE => self.max_frame = value as usize,
+                        _ => {}
+                    }
+                }
+                self.write_frame(FRAME_SETTINGS, FLAG_ACK, 0, &[])?;
+            }
+            FRAME_PING if flags & FLAG_ACK == 0 => {
+                self.write_frame(FRAME_PING, FLAG_ACK, 0, &payload)?;
+            }
+            FRAME_GOAWAY => return Err("server sent GOAWAY".into()),
+            FRAME_WINDOW_UPDATE => {
+                let increment = match payload[..] {
+                    [a, b, c, d] => u32::from_be_bytes([a, b, c, d]) & 0x7fff_ffff,
+                    _ => return Err("bad WINDOW_UPDATE frame".into()),
+                };
+                match id {
+                    0 => self.window += increment as i64,
+                    _ => {
+                        if let Some(s) = self.streams.get_mut(&id) {
+                            s.window += increment as i64;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if flags & FLAG_END_STREAM != 0 && (kind == FRAME_DATA || kind == FRAME_HEADERS) {
+            self.streams.remove(&id);
+        }
+
+        Ok(())
+    }
+
+    fn decode_headers(&mut self, id: u32) -> Result<(), Box<dyn Error>> {
+        let headers = self
+            .decoder
+            .decode(&self.header_block)
+            .map_err(|e| format!("HPACK {e:?}"))?;
+
+        // Trailers carry no status.
+        let status = match headers.iter().find(|(n, _)| n == b":status") {
+            Some((_, value)) => String::from_utf8_lossy(value).into_owned(),
+            None => return Ok(()),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("HTTP/2 stream {id} :status {status}");
+
+        let failure = match status.parse() {
+            Ok(code) => status_failure(code),
+            Err(_) => Some("异常"),
+        };
+        if let Some(f) = failure {
+            self.failure = Some(f);
+            return Err(format!("HTTP/2 stream {id} status {status}").into());
+        }
+
+        Ok(())
+    }
+}
+
+fn strip_padding(flags: u8, payload: &[u8]) -> Result<&[u8], Box<dyn Error>> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+
+    let padding = *payload.first().ok_or("bad padding")? as usize;
+    payload
+        .get(1..payload.len().saturating_sub(padding))
+        .ok_or_else(|| "bad padding".into())
+}
+
+fn request_h2_load(address: SocketAddr, url: Url, streams: u8, upload: bool, counter: LoadWorker) {
+    let request_chunk = "0123456789AaBbCcDdEeFfGgHhIiJjKkLlMmNnOoPpQqRrSsTtUuVvWwXxYyZz-="
+        .repeat(1024)
+        .into_bytes();
+
+    let stream = match make_alpn_connection(&address, &url, vec![b"h2".to_vec()]) {
+        Ok(s) => s,
+        Err(_) => {
+            counter.give_up();
+            return;
+        }
+    };
+
+    let mut conn = match H2Connection::handshake(stream) {
+        Ok(c) => c,
+        Err(_e) => {
+            #[cfg(debug_assertions)]
+            debug!("HTTP/2 Error: {}", _e);
+
+            counter.wait();
+            return;
+        }
+    };
+
+    counter.wait();
+
+    let download_counter = match upload {
+        true => None,
+        false => Some(&counter),
+    };
+
+    while !counter.is_end() {
+        let limit = (streams as usize).min(conn.max_streams);
+        while conn.streams.len() < limit {
+            if conn.open_stream(&url, upload).is_err() {
+                return;
+            }
+        }
+
+        if upload {
+            if let Some(id) = conn.sendable_stream() {
+                match conn.send_data(id, &request_chunk) {
+                    Ok(size) => counter.increase(size as u64),
+                    Err(_) => return,
+                }
+                continue;
+            }
+        }
+
+        let r = conn
+            .read_frame()
+            .and_then(|frame| conn.handle_frame(frame, download_counter));
+        if let Err(_e) = r {
+            #[cfg(debug_assertions)]
+            debug!("HTTP/2 Error: {}", _e);
+
+            if let Some(status) = conn.failure {
+                counter.fail(status);
+            }
+            return;
+        }
+    }
+
+    let _ = conn.write_frame(FRAME_GOAWAY, 0, 0, &[0; 8]);
+}
@@ -0,0 +1,374 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::clients::base::{
+    get_address, load_duration, make_connection, measure_latency, request_tcp_ping, AddressFamily,
+    Client, GenericStream, LoadCounter, LoadTiming, LoadWorker,
+};
+use crate::utils::{FlowReport, LatencyStats, SpeedTestResult, TestDuration};
+
+use std::io::{Read, Write};
+use std::time::SystemTime;
+
+const TEST_START: i8 = 1;
+const TEST_RUNNING: i8 = 2;
+const TEST_END: i8 = 4;
+const PARAM_EXCHANGE: i8 = 9;
+const CREATE_STREAMS: i8 = 10;
+const EXCHANGE_RESULTS: i8 = 13;
+const DISPLAY_RESULTS: i8 = 14;
+const IPERF_DONE: i8 = 16;
+const ACCESS_DENIED: i8 = -1;
+const SERVER_ERROR: i8 = -2;
+
+const COOKIE_SIZE: usize = 37;
+const BLOCK_SIZE: usize = 128 * 1024;
+/// Seconds the server keeps going after the load test would have ended.
+const TIME_MARGIN: u64 = 1;
+
+pub struct Iperf3Client {
+    url: Url,
+    threads: u8,
+
+    address: SocketAddr,
+
+    upload: f64,
+    upload_status: String,
+    download: f64,
+    download_status: String,
+    duration: TestDuration,
+    upload_flows: FlowReport,
+    download_flows: FlowReport,
+    latency: f64,
+    jitter: f64,
+    latency_stats: Option<LatencyStats>,
+}
+
+impl Iperf3Client {
+    pub fn build(url: String, family: AddressFamily, threads: u8) -> Option<Box<dyn Client>> {
+        let mut url = Url::parse(&url).ok()?;
+        if url.port().is_none() {
+            url.set_port(Some(5201)).ok()?;
+        }
+
+        let address = get_address(&url, family)?;
+
+        #[cfg(debug_assertions)]
+        debug!("IP address {address}");
+
+        let r = "取消".to_owned();
+        Some(Box::new(Self {
+            url,
+            threads,
+            address,
+            upload: 0.0,
+            upload_status: r.clone(),
+            download: 0.0,
+            download_status: r,
+            duration: TestDuration::default(),
+            upload_flows: FlowReport::default(),
+            download_flows: FlowReport::default(),
+            latency: 0.0,
+            jitter: 0.0,
+            latency_stats: None,
+        }))
+    }
+
+    fn run_load(&mut self, load: u8) -> Result<bool, Box<dyn Error>> {
+        let reverse = load != 0;
+        let cookie = make_cookie();
+
+        let mut control = make_connection(&self.address, &self.url)?;
+        control.write_all(&cookie)?;
+
+        expect_state(&mut control, PARAM_EXCHANGE)?;
+        let mut params = json!({
+            "tcp": true,
+            "omit": 0,
+            "time": test_time(),
+            "parallel": self.threads,
+            "len": BLOCK_SIZE,
+            "client_version": "3.9",
+        });
+        if reverse {
+            params["reverse"] = json!(true);
+        }
+        write_json(&mut control, &params)?;
+
+        expect_state(&mut control, CREATE_STREAMS)?;
+
+        let counter = Arc::new(LoadCounter::new(self.threads));
+        let mut tasks = vec![];
+
+        for _ in 0..self.threads {
+            let a = self.address;
+            let u = self.url.clone();
+            let c = counter.worker();
+
+            let task = thread::spawn(move || request_iperf3_stream(a, u, cookie, reverse, c));
+            tasks.push(task);
+            thread::sleep(Duration::from_millis(250));
+        }
+
+        counter.wait();
+
+        // The workers are running now, so end and join them before giving up.
+        let started = expect_state(&mut control, TEST_START)
+            .and_then(|_| expect_state(&mut control, TEST_RUNNING));
+        let time_passed = match started {
+            Ok(_) => counter.measure(),
+            Err(_) => 0,
+        };
+
+        counter.end();
+
+        // Keep the data connections open until the server has finished with
+        // the results, as iperf3 treats an early close as a failed test.
+        let mut streams = vec![];
+        let mut results = vec![];
+        for task in tasks {
+            if let Ok(Some((bytes, stream))) = task.join() {
+                let id = match streams.len() {
+                    0 => 1,
+                    n => n + 2,
+                };
+                results.push(json!({
+                    "id": id,
+                    "bytes": bytes,
+                    "retransmits": 0,
+                    "jitter": 0,
+                    "errors": 0,
+                    "packets": 0,
+                    "start_time": 0,
+                    "end_time": time_passed as f64 / 1_000_000.0,
+                }));
+                streams.push(stream);
+            }
+        }
+        started?;
+
+        write_state(&mut control, TEST_END)?;
+
+        expect_state(&mut control, EXCHANGE_RESULTS)?;
+        write_json(
+            &mut control,
+            &json!({
+                "cpu_util_total": 0,
+                "cpu_util_user": 0,
+                "cpu_util_system": 0,
+                "sender_has_retransmits": 0,
+                "streams": results,
+            }),
+        )?;
+        let _server_results = read_json(&mut control)?;
+
+        #[cfg(debug_assertions)]
+        debug!("Server results {_server_results}");
+
+        expect_state(&mut control, DISPLAY_RESULTS)?;
+        write_state(&mut control, IPERF_DONE)?;
+
+        match load {
+            0 => {
+                self.upload = counter.speed();
+                self.upload_status = counter.status();
+                self.duration.upload = time_passed as f64 / 1_000_000.0;
+                self.upload_flows = counter.flows();
+            }
+            _ => {
+                self.download = counter.speed();
+                self.download_status = counter.status();
+                self.duration.download = time_passed as f64 / 1_000_000.0;
+                self.download_flows = counter.flows();
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Client for Iperf3Client {
+    fn ping(&mut self) -> bool {
+        let stats = measure_latency(&self.address, &self.url, |_| {
+            request_tcp_ping(&self.address, &self.url)
+        });
+
+        self.latency = stats.min;
+        self.jitter = stats.jitter;
+
+        let ok = stats.failed < stats.samples;
+        self.latency_stats = Some(stats);
+        ok
+    }
+
+    fn download(&mut self) -> bool {
+        match self.run_load(1) {
+            Ok(_) => true,
+            Err(_e) => {
+                #[cfg(debug_assertions)]
+                debug!("Download Error: {}", _e);
+
+                self.download_status = "失败".to_string();
+                false
+            }
+        }
+    }
+
+    fn upload(&mut self) -> bool {
+        match self.run_load(0) {
+            Ok(_) => true,
+            Err(_e) => {
+                #[cfg(debug_assertions)]
+                debug!("Upload Error: {}", _e);
+
+                self.upload_status = "失败".to_string();
+                false
+            }
+        }
+    }
+
+    fn result(&self) -> SpeedTestResult {
+        let mut r = SpeedTestResult::build(
+            self.upload,
+            self.upload_status.clone(),
+            self.download,
+            self.download_status.clone(),
+            self.latency,
+            self.jitter,
+        );
+        r.set_duration(self.duration.clone());
+        r.set_flows(self.upload_flows.clone(), self.download_flows.clone());
+        if let Some(stats) = &self.latency_stats {
+            r.set_latency_stats(stats.clone());
+        }
+        r
+    }
+}
+
+/// Seconds of the test asked of the server, the load test rounded up so the
+/// server never stops first.
+fn test_time() -> u64 {
+    let duration = load_duration().unwrap_or(LoadTiming::default().duration);
+    duration.as_secs_f64().ceil() as u64 + TIME_MARGIN
+}
+
+/// A 36 character base32 cookie followed by NUL, as iperf3 expects.
+fn make_cookie() -> [u8; COOKIE_SIZE] {
+    let charset = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+        | 1;
+
+    let mut cookie = [0; COOKIE_SIZE];
+    for c in cookie.iter_mut().take(COOKIE_SIZE - 1) {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        *c = charset[(seed % 32) as usize];
+    }
+    cookie
+}
+
+fn write_state(stream: &mut Box<dyn GenericStream>, state: i8) -> Result<(), Box<dyn Error>> {
+    stream.write_all(&state.to_be_bytes())?;
+    Ok(())
+}
+
+fn expect_state(stream: &mut Box<dyn GenericStream>, expected: i8) -> Result<(), Box<dyn Error>> {
+    let mut state = [0; 1];
+    stream.read_exact(&mut state)?;
+    let state = state[0] as i8;
+
+    #[cfg(debug_assertions)]
+    debug!("Iperf3 state {state}");
+
+    match state {
+        s if s == expected => Ok(()),
+        ACCESS_DENIED => Err("服务器忙".into()),
+        SERVER_ERROR => Err("服务器错误".into()),
+        _ => Err(format!("unexpected iperf3 state {state}").into()),
+    }
+}
+
+fn write_json(stream: &mut Box<dyn GenericStream>, value: &Value) -> Result<(), Box<dyn Error>> {
+    let data = value.to_string().into_bytes();
+    stream.write_all(&(data.len() as u32).to_be_bytes())?;
+    stream.write_all(&data)?;
+    Ok(())
+}
+
+fn read_json(stream: &mut Box<dyn GenericStream>) -> Result<Value, Box<dyn Error>> {
+    let mut size = [0; 4];
+    stream.read_exact(&mut size)?;
+
+    let mut data = vec![0; u32::from_be_bytes(size) as usize];
+    stream.read_exact(&mut data)?;
+
+    Ok(serde_json::from_slice(&data)?)
+}
+
+fn request_iperf3_stream(
+    address: SocketAddr,
+    url: Url,
+    cookie: [u8; COOKIE_SIZE],
+    reverse: bool,
+    counter: LoadWorker,
+) -> Option<(u64, Box<dyn GenericStream>)> {
+    let mut data_counter = 0;
+    let mut buffer = [0; 65536];
+    let request_chunk = "0123456789AaBbCcDdEeFfGgHhIiJjKkLlMmNnOoPpQqRrSsTtUuVvWwXxYyZz-="
+        .repeat(BLOCK_SIZE / 64)
+        .into_bytes();
+
+    let mut stream = match make_connection(&address, &url) {
+        Ok(s) => s,
+        Err(_) => {
+            counter.give_up();
+            return None;
+        }
+    };
+
+    if stream.write_all(&cookie).is_err() {
+        counter.wait();
+        return None;
+    }
+
+    counter.wait();
+
+    #[cfg(debug_assertions)]
+    debug!("Iperf3 stream start, reverse {reverse}");
+
+    while !counter.is_end() {
+        let r = match reverse {
+            true => stream.read(&mut buffer),
+            false => stream.write(&request_chunk),
+        };
+        match r {
+            Ok(0) => break,
+            Ok(size) => {
+                let count = size as u64;
+                data_counter += count;
+                counter.increase(count);
+            }
+            Err(_e) => {
+                #[cfg(debug_assertions)]
+                debug!("Iperf3 stream error: {}", _e);
+
+                break;
+            }
+        }
+    }
+
+    Some((data_counter, stream))
+}
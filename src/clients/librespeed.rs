@@ -0,0 +1,389 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use url::Url;
+
+use crate::clients::base::{
+    get_address, host_port, make_connection, measure_latency, random_query, request_http_ping,
+    AddressFamily, Client, LoadCounter, LoadWorker,
+};
+use crate::clients::response::{read_body, read_response_head, response_error};
+use crate::utils::{FlowReport, LatencyStats, Saturation, SpeedTestResult, TestDuration};
+
+use std::io::{Read, Write};
+
+pub struct LibreSpeedClient {
+    download_url: Url,
+    upload_url: Url,
+    ip_url: Url,
+    threads: u8,
+
+    address: SocketAddr,
+
+    upload: f64,
+    upload_status: String,
+    download: f64,
+    download_status: String,
+    duration: TestDuration,
+    upload_flows: FlowReport,
+    download_flows: FlowReport,
+    saturation: Option<Saturation>,
+    latency: f64,
+    jitter: f64,
+    latency_stats: Option<LatencyStats>,
+    client_ip: Option<String>,
+}
+
+impl LibreSpeedClient {
+    pub fn build(url: String, family: AddressFamily, threads: u8) -> Option<Box<dyn Client>> {
+        let mut url = Url::parse(&url).ok()?;
+
+        // The backend scripts sit in the directory given, with or without a
+        // trailing slash.
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+
+        let download_url = url.join("garbage.php").ok()?;
+        let upload_url = url.join("empty.php").ok()?;
+        let ip_url = url.join("getIP.php").ok()?;
+
+        let address = get_address(&url, family)?;
+
+        #[cfg(debug_assertions)]
+        debug!("IP address {address}");
+
+        let r = "取消".to_owned();
+        Some(Box::new(Self {
+            download_url,
+            upload_url,
+            ip_url,
+            threads,
+            address,
+            upload: 0.0,
+            upload_status: r.clone(),
+            download: 0.0,
+            download_status: r,
+            duration: TestDuration::default(),
+            upload_flows: FlowReport::default(),
+            download_flows: FlowReport::default(),
+            saturation: None,
+            latency: 0.0,
+            jitter: 0.0,
+            latency_stats: None,
+            client_ip: None,
+        }))
+    }
+
+    fn run_load(&mut self, load: u8) -> Result<bool, Box<dyn Error>> {
+        let url = match load {
+            0 => self.upload_url.clone(),
+            _ => self.download_url.clone(),
+        };
+        let counter = Arc::new(LoadCounter::with_ramp_up(self.threads));
+        let tasks = counter.start(self.threads, |c| {
+            let a = self.address;
+            let u = url.clone();
+
+            thread::spawn(move || {
+                match load {
+                    0 => request_librespeed_upload(a, u, c),
+                    _ => request_librespeed_download(a, u, c),
+                };
+            })
+        });
+
+        let time_passed = counter.measure();
+
+        counter.end();
+        for task in tasks {
+            let _ = task.join();
+        }
+
+        match load {
+            0 => {
+                self.upload = counter.speed();
+                self.upload_status = counter.status();
+                self.duration.upload = time_passed as f64 / 1_000_000.0;
+                self.upload_flows = counter.flows();
+                if let Some(n) = counter.saturation() {
+                    self.saturation
+                        .get_or_insert_with(Saturation::default)
+                        .upload = n;
+                }
+            }
+            _ => {
+                self.download = counter.speed();
+                self.download_status = counter.status();
+                self.duration.download = time_passed as f64 / 1_000_000.0;
+                self.download_flows = counter.flows();
+                if let Some(n) = counter.saturation() {
+                    self.saturation
+                        .get_or_insert_with(Saturation::default)
+                        .download = n;
+                }
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Client for LibreSpeedClient {
+    fn ping(&mut self) -> bool {
+        let stats = measure_latency(&self.address, &self.upload_url, |_| {
+            let query = format!("cors=true&r={}", random_query());
+            request_http_ping(&self.address, &self.upload_url, &query)
+        });
+
+        self.latency = stats.min;
+        self.jitter = stats.jitter;
+
+        let ok = stats.failed < stats.samples;
+        self.latency_stats = Some(stats);
+        if !ok {
+            return false;
+        }
+
+        self.client_ip = request_librespeed_ip(&self.address, &self.ip_url);
+
+        #[cfg(debug_assertions)]
+        debug!("Client IP {:?}", self.client_ip);
+
+        true
+    }
+
+    fn download(&mut self) -> bool {
+        self.run_load(1).is_ok()
+    }
+
+    fn upload(&mut self) -> bool {
+        self.run_load(0).is_ok()
+    }
+
+    fn result(&self) -> SpeedTestResult {
+        let mut r = SpeedTestResult::build(
+            self.upload,
+            self.upload_status.clone(),
+            self.download,
+            self.download_status.clone(),
+            self.latency,
+            self.jitter,
+        );
+        if let Some(ip) = &self.client_ip {
+            r.set_client_ip(ip.clone());
+        }
+        r.set_duration(self.duration.clone());
+        r.set_flows(self.upload_flows.clone(), self.download_flows.clone());
+        if let Some(saturation) = &self.saturation {
+            r.set_saturation(saturation.clone());
+        }
+        if let Some(stats) = &self.latency_stats {
+            r.set_latency_stats(stats.clone());
+        }
+        r
+    }
+}
+
+/// Ask `getIP.php` for the public address the server sees. HTTP/1.0 is used
+/// so the body is never chunked and ends with the connection.
+fn request_librespeed_ip(address: &SocketAddr, url: &Url) -> Option<String> {
+    let mut stream = make_connection(address, url).ok()?;
+
+    let request_head = format!(
+        "GET {}?r={} HTTP/1.0\r\nHost: {}\r\nUser-Agent: bim/1.0\r\n\r\n",
+        url.path(),
+        random_query(),
+        host_port(url),
+    )
+    .into_bytes();
+    stream.write_all(&request_head).ok()?;
+
+    let mut response = vec![];
+    let _ = stream.read_to_end(&mut response);
+    let response = String::from_utf8_lossy(&response);
+    let (_, body) = response.split_once("\r\n\r\n")?;
+
+    let ip = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(v) => v["processedString"].as_str()?.to_string(),
+        Err(_) => body.to_string(),
+    };
+    let ip = ip.split_whitespace().next()?.to_string();
+
+    Some(ip)
+}
+
+fn request_librespeed_download(address: SocketAddr, url: Url, counter: LoadWorker) {
+    let chunk_count = 100;
+
+    let mut stream = match make_connection(&address, &url) {
+        Ok(s) => s,
+        Err(_) => {
+            counter.give_up();
+            return;
+        }
+    };
+
+    let mut pending = vec![];
+    counter.wait();
+
+    while !counter.is_end() {
+        let path_query = format!(
+            "{}?cors=true&r={}&ckSize={}",
+            url.path(),
+            random_query(),
+            chunk_count
+        );
+
+        #[cfg(debug_assertions)]
+        debug!("Download {path_query}");
+
+        let request_head = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bim/1.0\r\nConnection: close\r\n\r\n",
+            path_query,
+            host_port(&url),
+        )
+        .into_bytes();
+
+        if let Err(_e) = stream.write_all(&request_head) {
+            #[cfg(debug_assertions)]
+            debug!("Download Error: {}", _e);
+
+            return;
+        }
+
+        let head = match read_response_head(&mut stream, &mut pending) {
+            Ok(r) => r,
+            Err(e) => return response_error(&counter, e),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Download Status: {} {}", head.status, head.reason);
+
+        if let Some(status) = head.failure_status() {
+            counter.fail(status);
+            return;
+        }
+
+        let r = read_body(&mut stream, &mut pending, head.body(), |size| {
+            counter.increase(size);
+            !counter.is_end()
+        });
+        match r {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => return response_error(&counter, e),
+        }
+
+        // garbage.php ends the response by closing, so open a new connection.
+        counter.reconnect();
+        pending.clear();
+        stream = match make_connection(&address, &url) {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+    }
+}
+
+fn request_librespeed_upload(address: SocketAddr, url: Url, counter: LoadWorker) {
+    let data_size = 20 * 1024 * 1024_u64;
+    let mut data_counter;
+
+    let request_chunk = "0123456789AaBbCcDdEeFfGgHhIiJjKkLlMmNnOoPpQqRrSsTtUuVvWwXxYyZz-="
+        .repeat(1024)
+        .into_bytes();
+
+    let mut stream = match make_connection(&address, &url) {
+        Ok(s) => s,
+        Err(_) => {
+            counter.give_up();
+            return;
+        }
+    };
+
+    let mut pending = vec![];
+    counter.wait();
+
+    while !counter.is_end() {
+        let path_query = format!("{}?cors=true&r={}", url.path(), random_query());
+
+        #[cfg(debug_assertions)]
+        debug!("Upload {path_query} size {data_size}");
+
+        let request_head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bim/1.0\r\nConnection: close\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+            path_query,
+            host_port(&url),
+            data_size
+        )
+        .into_bytes();
+
+        match stream.write_all(&request_head) {
+            Ok(_) => {
+                data_counter = 0;
+                counter.increase(request_head.len() as u64);
+            }
+            Err(_e) => {
+                #[cfg(debug_assertions)]
+                debug!("Upload Error: {}", _e);
+
+                return;
+            }
+        }
+
+        while data_counter < data_size && !counter.is_end() {
+            let left = (data_size - data_counter).min(request_chunk.len() as u64) as usize;
+            match stream.write(&request_chunk[..left]) {
+                Ok(size) => {
+                    let count = size as u64;
+                    data_counter += count;
+                    counter.increase(count);
+                }
+                Err(_e) => {
+                    #[cfg(debug_assertions)]
+                    debug!("Upload Error: {}", _e);
+
+                    return;
+                }
+            }
+        }
+
+        if counter.is_end() {
+            return;
+        }
+
+        let head = match read_response_head(&mut stream, &mut pending) {
+            Ok(r) => r,
+            Err(e) => return response_error(&counter, e),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Upload Status: {} {}", head.status, head.reason);
+
+        if let Some(status) = head.failure_status() {
+            counter.fail(status);
+            return;
+        }
+
+        match read_body(&mut stream, &mut pending, head.body(), |_| {
+            !counter.is_end()
+        }) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => return response_error(&counter, e),
+        }
+
+        counter.reconnect();
+        pending.clear();
+        stream = match make_connection(&address, &url) {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+    }
+}
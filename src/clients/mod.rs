@@ -1,7 +1,37 @@
 mod base;
+mod cloudflare;
 mod http;
+mod http2;
+mod http_speedtest_net;
+mod iperf3;
+mod librespeed;
+mod probe;
+mod proxy;
+#[cfg(feature = "quic")]
+mod quic;
+mod resolve;
+mod response;
+mod source;
 mod tcp_speedtest_net;
+mod tls;
+mod udp;
 
-pub use base::Client;
+pub use base::{
+    set_latency_samples, set_load_timing, set_ramp_up, take_connections, Adaptive, AddressFamily,
+    Client, LoadTiming, RampUp,
+};
+pub use cloudflare::CloudflareClient;
 pub use http::HTTPClient;
+pub use http2::HTTP2Client;
+pub use http_speedtest_net::SpeedtestNetHttpClient;
+pub use iperf3::Iperf3Client;
+pub use librespeed::LibreSpeedClient;
+pub use probe::{set_latency_probe, LatencyProbe};
+pub use proxy::set_proxy;
+#[cfg(feature = "quic")]
+pub use quic::QuicClient;
+pub use resolve::{add_resolve, override_host, pin_address, resolve_all};
+pub use source::set_source;
 pub use tcp_speedtest_net::SpeedtestNetTcpClient;
+pub use tls::{set_tls_options, TlsOptions};
+pub use udp::UdpClient;
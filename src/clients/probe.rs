@@ -0,0 +1,248 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use url::{Position, Url};
+
+use crate::clients::base::{make_connection, request_tcp_ping, GenericStream};
+use crate::clients::response::read_response_head;
+use crate::clients::source;
+use crate::packet::{Packet, KIND_ECHO};
+
+static PROBE: OnceLock<LatencyProbe> = OnceLock::new();
+
+/// How a latency sample is taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyProbe {
+    /// Time to open a TCP connection.
+    Tcp,
+    /// Round trip of a `HEAD` request on a keep-alive connection.
+    Http,
+    /// ICMP echo through an unprivileged datagram socket, linux only.
+    Icmp,
+    /// bim UDP echo on the port of the test.
+    Udp,
+}
+
+impl FromStr for LatencyProbe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(LatencyProbe::Tcp),
+            "http" => Ok(LatencyProbe::Http),
+            "icmp" => Ok(LatencyProbe::Icmp),
+            "udp" => Ok(LatencyProbe::Udp),
+            _ => Err(format!("unknown latency probe {s}")),
+        }
+    }
+}
+
+/// Measure latency of every client with `probe` instead of its own.
+pub fn set_latency_probe(probe: LatencyProbe) -> Result<(), String> {
+    if cfg!(not(target_os = "linux")) && probe == LatencyProbe::Icmp {
+        return Err(String::from(
+            "icmp latency probe is only supported on linux",
+        ));
+    }
+
+    PROBE
+        .set(probe)
+        .map_err(|_| String::from("latency probe already set"))
+}
+
+/// Takes the samples of the probe given to `set_latency_probe`, keeping the
+/// connection or socket open between samples.
+pub struct Prober<'a> {
+    probe: LatencyProbe,
+    address: &'a SocketAddr,
+    url: &'a Url,
+    stream: Option<Box<dyn GenericStream>>,
+    socket: Option<UdpSocket>,
+    #[cfg(target_os = "linux")]
+    icmp: Option<socket2::Socket>,
+}
+
+impl<'a> Prober<'a> {
+    /// `None` unless a probe was chosen, in which case clients use their own.
+    pub fn build(address: &'a SocketAddr, url: &'a Url) -> Option<Self> {
+        Some(Self {
+            probe: *PROBE.get()?,
+            address,
+            url,
+            stream: None,
+            socket: None,
+            #[cfg(target_os = "linux")]
+            icmp: None,
+        })
+    }
+
+    /// Round trip time of sample `seq` in microseconds, 0 if it failed.
+    pub fn probe(&mut self, seq: u64) -> u128 {
+        match self.probe {
+            LatencyProbe::Tcp => request_tcp_ping(self.address, self.url),
+            LatencyProbe::Http => self.http_ping(),
+            LatencyProbe::Icmp => self.icmp_ping(seq),
+            LatencyProbe::Udp => self.udp_ping(seq),
+        }
+    }
+
+    fn http_ping(&mut self) -> u128 {
+        // The connection is opened outside of the timed request.
+        if self.stream.is_none() {
+            self.stream = make_connection(self.address, self.url).ok();
+        }
+        let mut stream = match self.stream.take() {
+            Some(s) => s,
+            None => return 0,
+        };
+
+        let request_head = format!(
+            "HEAD {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bim/1.0\r\n\r\n",
+            &self.url[Position::BeforePath..Position::AfterQuery],
+            &self.url[Position::BeforeHost..Position::AfterPort],
+        );
+
+        let now = Instant::now();
+        if stream.write_all(request_head.as_bytes()).is_err() {
+            return 0;
+        }
+        match read_response_head(&mut stream, &mut vec![]) {
+            Ok(head) => {
+                let used = now.elapsed().as_micros();
+                if head.keep_alive() {
+                    self.stream = Some(stream);
+                }
+                used
+            }
+            Err(_e) => {
+                #[cfg(debug_assertions)]
+                debug!("Ping {_e}");
+
+                0
+            }
+        }
+    }
+
+    fn udp_ping(&mut self, seq: u64) -> u128 {
+        if self.socket.is_none() {
+            self.socket = source::udp_bind(self.address)
+                .and_then(|s| {
+                    s.connect(self.address)?;
+                    s.set_read_timeout(Some(Duration::from_secs(1)))?;
+                    Ok(s)
+                })
+                .ok();
+        }
+
+        match &self.socket {
+            Some(socket) => request_udp_ping(socket, seq),
+            None => 0,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn icmp_ping(&mut self, seq: u64) -> u128 {
+        use socket2::{Protocol, Type};
+
+        if self.icmp.is_none() {
+            let protocol = match self.address {
+                SocketAddr::V4(_) => Protocol::ICMPV4,
+                SocketAddr::V6(_) => Protocol::ICMPV6,
+            };
+            self.icmp = source::make_socket(self.address, Type::DGRAM, protocol)
+                .and_then(|s| {
+                    s.connect(&SocketAddr::new(self.address.ip(), 0).into())?;
+                    s.set_read_timeout(Some(Duration::from_secs(1)))?;
+                    Ok(s)
+                })
+                .ok();
+        }
+
+        match &self.icmp {
+            Some(socket) => request_icmp_ping(socket, self.address, seq as u16),
+            None => 0,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn icmp_ping(&mut self, _seq: u64) -> u128 {
+        0
+    }
+}
+
+/// Round trip of an ICMP echo on a linux ping socket, which fills in the
+/// identifier and leaves out the IP header.
+#[cfg(target_os = "linux")]
+fn request_icmp_ping(socket: &socket2::Socket, address: &SocketAddr, seq: u16) -> u128 {
+    let (request_type, reply_type) = match address {
+        SocketAddr::V4(_) => (8, 0),
+        SocketAddr::V6(_) => (128, 129),
+    };
+
+    let mut request = vec![request_type, 0, 0, 0, 0, 0];
+    request.extend_from_slice(&seq.to_be_bytes());
+    request.extend_from_slice(b"bim-ping");
+    let checksum = icmp_checksum(&request);
+    request[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    let mut socket = socket;
+    let now = Instant::now();
+    if socket.write_all(&request).is_err() {
+        return 0;
+    }
+
+    let mut reply = [0; 1500];
+    while let Ok(size) = socket.read(&mut reply) {
+        if size >= 8 && reply[0] == reply_type && reply[6..8] == seq.to_be_bytes() {
+            return now.elapsed().as_micros();
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    debug!("Ping timeout");
+
+    0
+}
+
+#[cfg(target_os = "linux")]
+fn icmp_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Round trip of a bim UDP echo packet.
+pub fn request_udp_ping(socket: &UdpSocket, seq: u64) -> u128 {
+    let mut buffer = [0; 1500];
+    let mut packet = Packet::new(KIND_ECHO, 0);
+    packet.seq = seq;
+    let size = packet.encode(&mut buffer);
+
+    let now = Instant::now();
+    if socket.send(&buffer[..size]).is_err() {
+        return 0;
+    }
+
+    while let Ok(size) = socket.recv(&mut buffer) {
+        match Packet::decode(&buffer[..size]) {
+            Some(p) if p.kind == KIND_ECHO && p.seq == seq => return now.elapsed().as_micros(),
+            _ => continue,
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    debug!("Ping timeout");
+
+    0
+}
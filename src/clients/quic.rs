@@ -0,0 +1,253 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use quinn::{Connection, Endpoint, EndpointConfig, TokioRuntime, TransportConfig, VarInt};
+use tokio::runtime::Runtime;
+use url::Url;
+
+use crate::clients::base::{
+    get_address, measure_latency, AddressFamily, Client, LoadCounter, LoadWorker,
+};
+use crate::clients::source;
+use crate::clients::tls::NoVerification;
+use crate::utils::{FlowReport, LatencyStats, SpeedTestResult, TestDuration};
+
+const ALPN: &[u8] = b"bim";
+const WINDOW_SIZE: u32 = 16 * 1024 * 1024;
+
+pub struct QuicClient {
+    url: Url,
+    threads: u8,
+
+    address: SocketAddr,
+    runtime: Runtime,
+    config: quinn::ClientConfig,
+
+    upload: f64,
+    upload_status: String,
+    download: f64,
+    download_status: String,
+    duration: TestDuration,
+    upload_flows: FlowReport,
+    download_flows: FlowReport,
+    latency: f64,
+    jitter: f64,
+    latency_stats: Option<LatencyStats>,
+}
+
+impl QuicClient {
+    pub fn build(url: String, family: AddressFamily, threads: u8) -> Option<Box<dyn Client>> {
+        let url = Url::parse(&url).ok()?;
+
+        let address = get_address(&url, family)?;
+
+        #[cfg(debug_assertions)]
+        debug!("IP address {address}");
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .ok()?;
+
+        let mut crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            // The bim QUIC listener presents a throwaway self-signed certificate.
+            .with_custom_certificate_verifier(Arc::new(NoVerification))
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+        let mut transport = TransportConfig::default();
+        transport
+            .stream_receive_window(VarInt::from_u32(WINDOW_SIZE))
+            .receive_window(VarInt::from_u32(WINDOW_SIZE * 4))
+            .send_window(WINDOW_SIZE as u64 * 4);
+
+        let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+        config.transport_config(Arc::new(transport));
+
+        let r = "取消".to_owned();
+        Some(Box::new(Self {
+            url,
+            threads,
+            address,
+            runtime,
+            config,
+            upload: 0.0,
+            upload_status: r.clone(),
+            download: 0.0,
+            download_status: r,
+            duration: TestDuration::default(),
+            upload_flows: FlowReport::default(),
+            download_flows: FlowReport::default(),
+            latency: 0.0,
+            jitter: 0.0,
+            latency_stats: None,
+        }))
+    }
+
+    fn connect(&self) -> Result<Connection, Box<dyn Error>> {
+        let server_name = self.url.host_str().ok_or("no host")?;
+
+        self.runtime.block_on(async {
+            let socket = source::udp_bind(&self.address)?;
+            let endpoint = Endpoint::new(EndpointConfig::default(), None, socket, TokioRuntime)?;
+            let connecting =
+                endpoint.connect_with(self.config.clone(), self.address, server_name)?;
+            let conn = tokio::time::timeout(Duration::from_secs(3), connecting).await??;
+            Ok(conn)
+        })
+    }
+
+    fn run_load(&mut self, load: u8) -> Result<bool, Box<dyn Error>> {
+        let conn = self.connect()?;
+        let counter = Arc::new(LoadCounter::new(0));
+        let mut tasks = vec![];
+
+        for _ in 0..self.threads {
+            let c = counter.worker();
+            let task = match load {
+                0 => self.runtime.spawn(request_quic_upload(conn.clone(), c)),
+                _ => self.runtime.spawn(request_quic_download(conn.clone(), c)),
+            };
+            tasks.push(task);
+        }
+
+        counter.wait();
+
+        let time_passed = counter.measure();
+
+        counter.end();
+        conn.close(VarInt::from_u32(0), b"done");
+        self.runtime.block_on(async {
+            for task in tasks {
+                let _ = task.await;
+            }
+        });
+
+        match load {
+            0 => {
+                self.upload = counter.speed();
+                self.upload_status = counter.status();
+                self.duration.upload = time_passed as f64 / 1_000_000.0;
+                self.upload_flows = counter.flows();
+            }
+            _ => {
+                self.download = counter.speed();
+                self.download_status = counter.status();
+                self.duration.download = time_passed as f64 / 1_000_000.0;
+                self.download_flows = counter.flows();
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Client for QuicClient {
+    fn ping(&mut self) -> bool {
+        let stats = measure_latency(&self.address, &self.url, |_| {
+            let now = Instant::now();
+            match self.connect() {
+                Ok(conn) => {
+                    let used = now.elapsed().as_micros();
+                    conn.close(VarInt::from_u32(0), b"ping");
+                    used
+                }
+                Err(_e) => {
+                    #[cfg(debug_assertions)]
+                    debug!("Ping {_e}");
+
+                    0
+                }
+            }
+        });
+
+        self.latency = stats.min;
+        self.jitter = stats.jitter;
+
+        let ok = stats.failed < stats.samples;
+        self.latency_stats = Some(stats);
+        ok
+    }
+
+    fn download(&mut self) -> bool {
+        self.run_load(1).is_ok()
+    }
+
+    fn upload(&mut self) -> bool {
+        self.run_load(0).is_ok()
+    }
+
+    fn result(&self) -> SpeedTestResult {
+        let mut r = SpeedTestResult::build(
+            self.upload,
+            self.upload_status.clone(),
+            self.download,
+            self.download_status.clone(),
+            self.latency,
+            self.jitter,
+        );
+        r.set_duration(self.duration.clone());
+        r.set_flows(self.upload_flows.clone(), self.download_flows.clone());
+        if let Some(stats) = &self.latency_stats {
+            r.set_latency_stats(stats.clone());
+        }
+        r
+    }
+}
+
+async fn request_quic_download(conn: Connection, counter: LoadWorker) {
+    let mut buffer = vec![0; 65536];
+
+    let (mut send, mut recv) = match conn.open_bi().await {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if send.write_all(b"DOWNLOAD\n").await.is_err() || send.finish().await.is_err() {
+        return;
+    }
+
+    while !counter.is_end() {
+        match recv.read(&mut buffer).await {
+            Ok(Some(size)) => counter.increase(size as u64),
+            Ok(None) => return,
+            Err(_e) => {
+                #[cfg(debug_assertions)]
+                debug!("Download Error: {}", _e);
+
+                return;
+            }
+        }
+    }
+}
+
+async fn request_quic_upload(conn: Connection, counter: LoadWorker) {
+    let request_chunk = "0123456789AaBbCcDdEeFfGgHhIiJjKkLlMmNnOoPpQqRrSsTtUuVvWwXxYyZz-="
+        .repeat(1024)
+        .into_bytes();
+
+    let (mut send, _recv) = match conn.open_bi().await {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if send.write_all(b"UPLOAD\n").await.is_err() {
+        return;
+    }
+
+    while !counter.is_end() {
+        match send.write(&request_chunk).await {
+            Ok(size) => counter.increase(size as u64),
+            Err(_e) => {
+                #[cfg(debug_assertions)]
+                debug!("Upload Error: {}", _e);
+
+                return;
+            }
+        }
+    }
+}
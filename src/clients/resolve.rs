@@ -0,0 +1,110 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use url::Url;
+
+use crate::clients::base::{get_addresses, AddressFamily};
+
+/// Host and port pinned to the host to connect to instead, like curl
+/// `--resolve`. A pin without a port applies to every port of the host.
+static PINNED: Mutex<Vec<(String, Option<u16>, String)>> = Mutex::new(vec![]);
+
+/// Pin `HOST:PORT:ADDR` so connections to that host and port go to the IP
+/// address `ADDR` without asking DNS.
+pub fn add_resolve(entry: &str) -> Result<(), String> {
+    let mut parts = entry.splitn(3, ':');
+    let (host, port, address) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(host), Some(port), Some(address)) if !host.is_empty() => (host, port, address),
+        _ => return Err(format!("{entry}: expected HOST:PORT:ADDR")),
+    };
+
+    let port = port
+        .parse()
+        .map_err(|_| format!("{entry}: invalid port {port}"))?;
+    let address: IpAddr = address
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|_| format!("{entry}: invalid address {address}"))?;
+
+    pin(host, Some(port), format_ip(address));
+    Ok(())
+}
+
+/// Rewrite `url` to use `host` for the `Host` header and TLS SNI while still
+/// connecting to the address of the original host. URLs on different hosts
+/// with the same port can not both be given the same `host`.
+pub fn override_host(url: &str, host: &str) -> Result<String, String> {
+    let mut url = Url::parse(url).map_err(|e| format!("{url}: {e}"))?;
+    let original = url.host_str().ok_or(format!("{url}: no host"))?.to_string();
+    let port = url
+        .port_or_known_default()
+        .ok_or(format!("{url}: no port"))?;
+
+    let pinned = PINNED.lock().unwrap().iter().find_map(|(h, p, target)| {
+        (h.eq_ignore_ascii_case(host) && *p == Some(port)).then(|| target.clone())
+    });
+    if let Some(target) = pinned.filter(|target| !target.eq_ignore_ascii_case(&original)) {
+        return Err(format!("{host}:{port} already connects to {target}"));
+    }
+
+    url.set_host(Some(host))
+        .map_err(|e| format!("{host}: {e}"))?;
+    pin(host, Some(port), original);
+
+    Ok(url.to_string())
+}
+
+/// Every IP address the host of `url` resolves to in `family`, to test
+/// them one by one with `pin_address`.
+pub fn resolve_all(url: &str, family: AddressFamily) -> Result<Vec<IpAddr>, String> {
+    let mut url = Url::parse(url).map_err(|e| format!("{url}: {e}"))?;
+    // Only the host matters here, a client may pick the port itself.
+    if url.port_or_known_default().is_none() {
+        let _ = url.set_port(Some(0));
+    }
+    let addresses = get_addresses(&url, family);
+    if addresses.is_empty() {
+        return Err(format!("{url}: no address found"));
+    }
+
+    Ok(addresses.iter().map(|address| address.ip()).collect())
+}
+
+/// Connect to `ip` for the host of `url` on any port, replacing any earlier
+/// pin of the host. Clients may connect to a port of their own, such as
+/// iperf3 to 5201.
+pub fn pin_address(url: &str, ip: IpAddr) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|e| format!("{url}: {e}"))?;
+    let host = url.host_str().ok_or(format!("{url}: no host"))?;
+
+    pin(host, None, format_ip(ip));
+    Ok(())
+}
+
+fn format_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{ip}]"),
+    }
+}
+
+fn pin(host: &str, port: Option<u16>, target: String) {
+    let host = host.to_ascii_lowercase();
+    let mut pinned = PINNED.lock().unwrap();
+    pinned.retain(|(h, p, _)| *h != host || (port.is_some() && *p != port));
+    pinned.push((host, port, target));
+}
+
+/// The host to look up in place of the one of `url`, if it is pinned.
+pub fn pinned_host(url: &Url) -> Option<String> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let port = url.port_or_known_default()?;
+
+    PINNED
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(h, p, _)| *h == host && p.is_none_or(|p| p == port))
+        .map(|(_, _, target)| target.clone())
+}
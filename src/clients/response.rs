@@ -0,0 +1,412 @@
+use std::fmt;
+use std::io;
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use crate::clients::base::{GenericStream, LoadWorker};
+
+const MAX_HEAD_SIZE: usize = 65536;
+
+#[derive(Debug)]
+pub enum ResponseError {
+    Io(io::Error),
+    Closed,
+    Malformed,
+}
+
+impl ResponseError {
+    /// Status to report for the test, `None` when the connection simply
+    /// broke and the usual stall detection applies.
+    pub fn status(&self) -> Option<&'static str> {
+        match self {
+            ResponseError::Malformed => Some("异常"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseError::Io(e) => write!(f, "{e}"),
+            ResponseError::Closed => write!(f, "连接关闭"),
+            ResponseError::Malformed => write!(f, "响应格式错误"),
+        }
+    }
+}
+
+impl From<io::Error> for ResponseError {
+    fn from(e: io::Error) -> Self {
+        ResponseError::Io(e)
+    }
+}
+
+impl From<ResponseError> for String {
+    fn from(e: ResponseError) -> Self {
+        e.to_string()
+    }
+}
+
+/// How the end of a response body is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Body {
+    Length(u64),
+    Chunked,
+    Close,
+}
+
+#[derive(Debug)]
+pub struct ResponseHead {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    headers: Vec<(String, String)>,
+}
+
+impl ResponseHead {
+    pub fn parse(head: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(head).ok()?;
+        let mut lines = text.split("\r\n");
+
+        let mut status_line = lines.next()?.splitn(3, ' ');
+        let version = status_line.next()?.to_string();
+        if !version.starts_with("HTTP/") {
+            return None;
+        }
+        let status = status_line.next()?.parse().ok()?;
+        let reason = status_line.next().unwrap_or_default().to_string();
+
+        let mut headers = vec![];
+        for line in lines.take_while(|l| !l.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+
+        Some(Self {
+            version,
+            status,
+            reason,
+            headers,
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn body(&self) -> Body {
+        if (100..200).contains(&self.status) || self.status == 204 || self.status == 304 {
+            return Body::Length(0);
+        }
+
+        let chunked = self
+            .header("transfer-encoding")
+            .map(|v| v.to_ascii_lowercase().contains("chunked"))
+            .unwrap_or(false);
+        if chunked {
+            return Body::Chunked;
+        }
+
+        match self.header("content-length").and_then(|v| v.parse().ok()) {
+            Some(length) => Body::Length(length),
+            None => Body::Close,
+        }
+    }
+
+    /// Status to report for the test when the response is not a success, so
+    /// an error page or a captive portal is never taken for test traffic.
+    pub fn failure_status(&self) -> Option<&'static str> {
+        status_failure(self.status)
+    }
+
+    /// Whether the connection can carry another request after this response.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self
+            .header("connection")
+            .map(|v| v.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match self.version.as_str() {
+            "HTTP/1.0" => connection.contains("keep-alive") && self.body() != Body::Close,
+            _ => !connection.contains("close") && self.body() != Body::Close,
+        }
+    }
+}
+
+/// Read and parse a response head, starting with the `pending` bytes read
+/// past the previous response on the connection. Body bytes that arrived
+/// in the same reads are left in `pending`.
+pub fn read_response_head(
+    stream: &mut Box<dyn GenericStream>,
+    pending: &mut Vec<u8>,
+) -> Result<ResponseHead, ResponseError> {
+    let mut buffer = [0; 16384];
+
+    let end = loop {
+        if let Some(end) = pending.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if pending.len() > MAX_HEAD_SIZE {
+            return Err(ResponseError::Malformed);
+        }
+
+        let size = stream.read(&mut buffer)?;
+        if size == 0 {
+            return Err(ResponseError::Closed);
+        }
+        pending.extend_from_slice(&buffer[..size]);
+    };
+
+    let head: Vec<u8> = pending.drain(..end).collect();
+    ResponseHead::parse(&head).ok_or(ResponseError::Malformed)
+}
+
+/// Read a response body framed as `body`, starting with the `pending` bytes
+/// left over from the head. `on_data` is told the size of every piece of
+/// body data and returns `false` to stop early. Bytes read past the end of
+/// the body stay in `pending` for the next response.
+///
+/// Returns `Ok(true)` once the whole body has been read.
+pub fn read_body<F>(
+    stream: &mut Box<dyn GenericStream>,
+    pending: &mut Vec<u8>,
+    body: Body,
+    mut on_data: F,
+) -> Result<bool, ResponseError>
+where
+    F: FnMut(u64) -> bool,
+{
+    let mut buffer = [0; 65536];
+
+    let mut fill = |pending: &mut Vec<u8>| -> Result<usize, ResponseError> {
+        let size = stream.read(&mut buffer)?;
+        pending.extend_from_slice(&buffer[..size]);
+        Ok(size)
+    };
+
+    match body {
+        Body::Length(length) => {
+            let mut left = length;
+            while left > 0 {
+                if pending.is_empty() && fill(pending)? == 0 {
+                    return Err(ResponseError::Closed);
+                }
+                let size = (pending.len() as u64).min(left);
+                pending.drain(..size as usize);
+                left -= size;
+                if !on_data(size) {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        Body::Close => loop {
+            if !pending.is_empty() {
+                let size = pending.len() as u64;
+                pending.clear();
+                if !on_data(size) {
+                    return Ok(false);
+                }
+            }
+            if fill(pending)? == 0 {
+                return Ok(true);
+            }
+        },
+        Body::Chunked => {
+            let mut data_left = 0;
+            let mut after_data = false;
+            let mut trailers = false;
+
+            loop {
+                if data_left > 0 {
+                    if pending.is_empty() && fill(pending)? == 0 {
+                        return Err(ResponseError::Closed);
+                    }
+                    let size = (pending.len() as u64).min(data_left);
+                    pending.drain(..size as usize);
+                    data_left -= size;
+                    if !on_data(size) {
+                        return Ok(false);
+                    }
+                    continue;
+                }
+
+                let end = match pending.windows(2).position(|w| w == b"\r\n") {
+                    Some(end) => end,
+                    None => {
+                        if pending.len() > MAX_HEAD_SIZE {
+                            return Err(ResponseError::Malformed);
+                        }
+                        if fill(pending)? == 0 {
+                            return Err(ResponseError::Closed);
+                        }
+                        continue;
+                    }
+                };
+                let line: Vec<u8> = pending.drain(..end + 2).take(end).collect();
+
+                if after_data {
+                    after_data = false;
+                } else if trailers {
+                    if line.is_empty() {
+                        return Ok(true);
+                    }
+                } else {
+                    let line = String::from_utf8_lossy(&line);
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    data_left =
+                        u64::from_str_radix(size, 16).map_err(|_| ResponseError::Malformed)?;
+                    match data_left {
+                        0 => trailers = true,
+                        _ => after_data = true,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Log a response that could not be read, recording a status for the test
+/// when the reply was malformed rather than just cut off.
+pub fn response_error(counter: &LoadWorker, e: ResponseError) {
+    #[cfg(debug_assertions)]
+    debug!("Response Error: {}", e);
+
+    if let Some(status) = e.status() {
+        counter.fail(status);
+    }
+}
+
+/// The test status for an HTTP response status that is not a success.
+pub fn status_failure(status: u16) -> Option<&'static str> {
+    match status {
+        200..=299 => None,
+        300..=399 => Some("跳转"),
+        _ => Some("错误"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn stream(bytes: &[u8]) -> Box<dyn GenericStream> {
+        Box::new(Cursor::new(bytes.to_vec()))
+    }
+
+    /// Read one response off `stream`, returning its head, the body size
+    /// and whether the whole body was read.
+    fn read_response(
+        stream: &mut Box<dyn GenericStream>,
+        pending: &mut Vec<u8>,
+    ) -> Result<(ResponseHead, u64, bool), ResponseError> {
+        let head = read_response_head(stream, pending)?;
+        let mut size = 0;
+        let done = read_body(stream, pending, head.body(), |n| {
+            size += n;
+            true
+        })?;
+        Ok((head, size, done))
+    }
+
+    #[test]
+    fn content_length_body_keeps_next_response() {
+        let mut s = stream(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello\
+              HTTP/1.1 404 Not Found\r\nContent-Length: 3\r\n\r\nbad",
+        );
+        let mut pending = vec![];
+
+        let (head, size, done) = read_response(&mut s, &mut pending).unwrap();
+        assert_eq!(head.status, 200);
+        assert_eq!(head.body(), Body::Length(5));
+        assert!(head.keep_alive());
+        assert_eq!((size, done), (5, true));
+
+        let (head, size, done) = read_response(&mut s, &mut pending).unwrap();
+        assert_eq!(head.status, 404);
+        assert_eq!(head.reason, "Not Found");
+        assert_eq!(head.failure_status(), Some("错误"));
+        assert_eq!((size, done), (3, true));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn chunked_body_with_extensions_and_trailers() {
+        let mut s = stream(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5;name=value\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n\
+              HTTP/1.1 204 No Content\r\n\r\n",
+        );
+        let mut pending = vec![];
+
+        let (head, size, done) = read_response(&mut s, &mut pending).unwrap();
+        assert_eq!(head.body(), Body::Chunked);
+        assert_eq!((size, done), (11, true));
+
+        let (head, size, done) = read_response(&mut s, &mut pending).unwrap();
+        assert_eq!(head.status, 204);
+        assert_eq!((size, done), (0, true));
+    }
+
+    #[test]
+    fn close_delimited_body() {
+        let mut s = stream(b"HTTP/1.0 200 OK\r\nServer: test\r\n\r\n0123456789");
+        let mut pending = vec![];
+
+        let (head, size, done) = read_response(&mut s, &mut pending).unwrap();
+        assert_eq!(head.body(), Body::Close);
+        assert!(!head.keep_alive());
+        assert_eq!((size, done), (10, true));
+    }
+
+    #[test]
+    fn body_cut_short() {
+        let mut s = stream(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello");
+        let r = read_response(&mut s, &mut vec![]);
+        assert!(matches!(r, Err(ResponseError::Closed)));
+
+        let mut s = stream(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\na\r\nhello");
+        let r = read_response(&mut s, &mut vec![]);
+        assert!(matches!(r, Err(ResponseError::Closed)));
+    }
+
+    #[test]
+    fn stop_early() {
+        let mut s = stream(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+        let mut pending = vec![];
+        let head = read_response_head(&mut s, &mut pending).unwrap();
+
+        let r = read_body(&mut s, &mut pending, head.body(), |_| false);
+        assert!(matches!(r, Ok(false)));
+    }
+
+    #[test]
+    fn malformed_heads() {
+        for response in [
+            &b"<html>captive portal</html>\r\n\r\n"[..],
+            b"HTTP/1.1 abc OK\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nno colon here\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+        ] {
+            let r = read_response(&mut stream(response), &mut vec![]);
+            assert!(
+                matches!(r, Err(ResponseError::Malformed)),
+                "{}",
+                String::from_utf8_lossy(response)
+            );
+        }
+
+        let endless = [b'x'; MAX_HEAD_SIZE + 2];
+        let r = read_response_head(&mut stream(&endless), &mut vec![]);
+        assert!(matches!(r, Err(ResponseError::Malformed)));
+
+        let r = read_response_head(&mut stream(b""), &mut vec![]);
+        assert!(matches!(r, Err(ResponseError::Closed)));
+    }
+}
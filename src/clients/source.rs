@@ -0,0 +1,95 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+static SOURCE: OnceLock<Source> = OnceLock::new();
+
+struct Source {
+    address: Option<IpAddr>,
+    interface: Option<String>,
+}
+
+/// Bind every socket to the local IP `address` and/or the network device
+/// `interface` before connecting, to test through one of several uplinks.
+pub fn set_source(address: Option<&str>, interface: Option<&str>) -> Result<(), String> {
+    let address = match address {
+        Some(a) => Some(
+            a.parse()
+                .map_err(|_| format!("invalid source address {a}"))?,
+        ),
+        None => None,
+    };
+
+    if cfg!(not(target_os = "linux")) && interface.is_some() {
+        return Err(String::from(
+            "binding to an interface is only supported on linux",
+        ));
+    }
+
+    SOURCE
+        .set(Source {
+            address,
+            interface: interface.map(str::to_string),
+        })
+        .map_err(|_| String::from("source already set"))
+}
+
+/// A socket of type `ty` to talk to `peer`, bound as `set_source` asked.
+pub fn make_socket(peer: &SocketAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(*peer), ty, Some(protocol))?;
+
+    let source = match SOURCE.get() {
+        Some(s) => s,
+        None => return Ok(socket),
+    };
+
+    #[cfg(target_os = "linux")]
+    if let Some(interface) = &source.interface {
+        socket.bind_device(Some(interface.as_bytes()))?;
+    }
+
+    if let Some(ip) = source.address {
+        if ip.is_ipv4() != peer.is_ipv4() {
+            return Err(io::Error::other(format!(
+                "source address {ip} can not reach {peer}"
+            )));
+        }
+
+        #[cfg(debug_assertions)]
+        debug!("Bind {ip} to {peer}");
+
+        socket.bind(&SocketAddr::new(ip, 0).into())?;
+    }
+
+    Ok(socket)
+}
+
+/// `TcpStream::connect_timeout` from the source address or interface.
+pub fn tcp_connect(address: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    if SOURCE.get().is_none() {
+        return TcpStream::connect_timeout(address, timeout);
+    }
+
+    let socket = make_socket(address, Type::STREAM, Protocol::TCP)?;
+    socket.connect_timeout(&(*address).into(), timeout)?;
+    Ok(socket.into())
+}
+
+/// A UDP socket able to reach `peer`, from the source address or interface.
+pub fn udp_bind(peer: &SocketAddr) -> io::Result<UdpSocket> {
+    let socket = make_socket(peer, Type::DGRAM, Protocol::UDP)?;
+    if SOURCE.get().and_then(|s| s.address).is_none() {
+        let local = match peer {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        socket.bind(&local.into())?;
+    }
+    Ok(socket.into())
+}
@@ -2,19 +2,24 @@ use std::error::Error;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 #[cfg(debug_assertions)]
 use log::debug;
 
 use url::Url;
 
-use crate::clients::base::{get_address, make_connection, request_tcp_ping, Client, LoadCounter};
-use crate::utils::SpeedTestResult;
+use crate::clients::base::{
+    get_address, make_connection, measure_latency, AddressFamily, Client, GenericStream,
+    LoadCounter, LoadWorker,
+};
+use crate::utils::{FlowReport, LatencyStats, Saturation, SpeedTestResult, TestDuration};
 
 use std::io::{Read, Write};
+use std::time::SystemTime;
 
 pub struct SpeedtestNetTcpClient {
+    url: Url,
     threads: u8,
 
     address: SocketAddr,
@@ -23,61 +28,60 @@ pub struct SpeedtestNetTcpClient {
     upload_status: String,
     download: f64,
     download_status: String,
+    duration: TestDuration,
+    upload_flows: FlowReport,
+    download_flows: FlowReport,
+    saturation: Option<Saturation>,
     latency: f64,
     jitter: f64,
+    latency_stats: Option<LatencyStats>,
+    server_version: Option<String>,
 }
 
 impl SpeedtestNetTcpClient {
-    pub fn build(url: String, ipv6: bool, threads: u8) -> Option<Box<dyn Client>> {
+    pub fn build(url: String, family: AddressFamily, threads: u8) -> Option<Box<dyn Client>> {
         let url = Url::parse(&url).ok()?;
 
-        let address = get_address(&url, ipv6)?;
+        let address = get_address(&url, family)?;
 
         #[cfg(debug_assertions)]
         debug!("IP address {address}");
 
         let r = "取消".to_owned();
         Some(Box::new(Self {
+            url,
             threads,
             address,
             upload: 0.0,
             upload_status: r.clone(),
             download: 0.0,
-            download_status: r.clone(),
+            download_status: r,
+            duration: TestDuration::default(),
+            upload_flows: FlowReport::default(),
+            download_flows: FlowReport::default(),
+            saturation: None,
             latency: 0.0,
             jitter: 0.0,
+            latency_stats: None,
+            server_version: None,
         }))
     }
 
     fn run_load(&mut self, load: u8) -> Result<bool, Box<dyn Error>> {
-        let counter = Arc::new(LoadCounter::new(self.threads));
-        let mut tasks = vec![];
+        let counter = Arc::new(LoadCounter::with_ramp_up(self.threads));
+        let tasks = counter.start(self.threads, |c| {
+            let a = self.address;
+            let u = self.url.clone();
 
-        for _ in 0..self.threads {
-            let a = self.address.clone();
-            let c = counter.clone();
-
-            let task = thread::spawn(move || {
+            thread::spawn(move || {
                 match load {
-                    0 => request_tcp_upload(a, c),
-                    _ => request_tcp_download(a, c),
+                    0 => request_tcp_upload(a, u, c),
+                    _ => request_tcp_download(a, u, c),
                 };
-            });
-            tasks.push(task);
-            thread::sleep(Duration::from_millis(250));
-        }
+            })
+        });
 
-        let mut time_passed = 0;
-
-        counter.wait();
-
-        let now = Instant::now();
-        while time_passed < 14_000_000 {
-            thread::sleep(Duration::from_millis(500));
-            time_passed = now.elapsed().as_micros();
-
-            counter.count(time_passed);
-        }
+        let time_passed = counter.measure();
 
         counter.end();
         for task in tasks {
@@ -88,10 +92,24 @@ impl SpeedtestNetTcpClient {
             0 => {
                 self.upload = counter.speed();
                 self.upload_status = counter.status();
+                self.duration.upload = time_passed as f64 / 1_000_000.0;
+                self.upload_flows = counter.flows();
+                if let Some(n) = counter.saturation() {
+                    self.saturation
+                        .get_or_insert_with(Saturation::default)
+                        .upload = n;
+                }
             }
             _ => {
                 self.download = counter.speed();
                 self.download_status = counter.status();
+                self.duration.download = time_passed as f64 / 1_000_000.0;
+                self.download_flows = counter.flows();
+                if let Some(n) = counter.saturation() {
+                    self.saturation
+                        .get_or_insert_with(Saturation::default)
+                        .download = n;
+                }
             }
         }
 
@@ -101,86 +119,158 @@ impl SpeedtestNetTcpClient {
 
 impl Client for SpeedtestNetTcpClient {
     fn ping(&mut self) -> bool {
-        let mut count = 0;
-        let mut pings = [0u128; 6];
-        let mut ping_min = 10000000;
-
-        while count < 6 {
-            let ping = request_tcp_ping(&self.address);
-            if ping > 0 {
-                if ping < ping_min {
-                    ping_min = ping
-                }
-                pings[count] = ping;
-            }
-            thread::sleep(Duration::from_millis(1000));
-            count += 1;
-        }
-
-        if pings == [0, 0, 0, 0, 0, 0] {
-            self.latency = 0.0;
-            self.jitter = 0.0;
+        let url = &self.url;
+        let mut stream = match make_connection(&self.address, url) {
+            Ok(s) => s,
+            Err(_) => return false,
+        };
+
+        self.server_version = request_tcp_hello(&mut stream);
+        if self.server_version.is_none() {
             return false;
         }
 
-        let mut jitter_all = 0;
-        for p in pings {
-            if p > 0 {
-                jitter_all += p - ping_min;
+        #[cfg(debug_assertions)]
+        debug!("Server version {:?}", self.server_version);
+
+        // A reply that missed its timeout would be read as the reply to the
+        // next PING, so start over on a new connection after a failure.
+        let mut stream = Some(stream);
+        let stats = measure_latency(&self.address, url, |_| {
+            if stream.is_none() {
+                stream = make_connection(&self.address, url)
+                    .ok()
+                    .and_then(|mut s| request_tcp_hello(&mut s).map(|_| s));
             }
-        }
-
-        self.latency = ping_min as f64 / 1_000.0;
-        self.jitter = jitter_all as f64 / 5_000.0;
+            let rtt = match stream.as_mut() {
+                Some(s) => request_tcp_ping(s),
+                None => return 0,
+            };
+            if rtt == 0 {
+                stream = None;
+            }
+            rtt
+        });
 
-        #[cfg(debug_assertions)]
-        debug!("Ping {} ms", self.latency);
+        if let Some(s) = stream.as_mut() {
+            request_tcp_quit(s);
+        }
 
-        #[cfg(debug_assertions)]
-        debug!("Jitter {} ms", self.jitter);
+        self.latency = stats.min;
+        self.jitter = stats.jitter;
 
-        true
+        let ok = stats.failed < stats.samples;
+        self.latency_stats = Some(stats);
+        ok
     }
 
     fn download(&mut self) -> bool {
-        match self.run_load(1) {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+        self.run_load(1).is_ok()
     }
 
     fn upload(&mut self) -> bool {
-        match self.run_load(0) {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+        self.run_load(0).is_ok()
     }
 
     fn result(&self) -> SpeedTestResult {
-        SpeedTestResult::build(
+        let mut r = SpeedTestResult::build(
             self.upload,
             self.upload_status.clone(),
             self.download,
             self.download_status.clone(),
             self.latency,
             self.jitter,
-        )
+        );
+        if let Some(version) = &self.server_version {
+            r.set_server_version(version.clone());
+        }
+        r.set_duration(self.duration.clone());
+        r.set_flows(self.upload_flows.clone(), self.download_flows.clone());
+        if let Some(saturation) = &self.saturation {
+            r.set_saturation(saturation.clone());
+        }
+        if let Some(stats) = &self.latency_stats {
+            r.set_latency_stats(stats.clone());
+        }
+        r
     }
 }
 
-fn request_tcp_download(address: SocketAddr, counter: Arc<LoadCounter>) {
-    let data_size = 15 * 1024 * 1024 * 1024 as u128;
+fn read_line(stream: &mut Box<dyn GenericStream>) -> Option<String> {
+    let mut line = vec![];
+    let mut byte = [0; 1];
+
+    while line.len() < 1024 {
+        match stream.read(&mut byte) {
+            Ok(1) if byte[0] == b'\n' => return String::from_utf8(line).ok(),
+            Ok(1) => line.push(byte[0]),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Send `HI` and return the version from the `HELLO <version> ...` reply,
+/// or `None` if the peer does not speak the Speedtest.net protocol.
+fn request_tcp_hello(stream: &mut Box<dyn GenericStream>) -> Option<String> {
+    stream.write_all(b"HI\n").ok()?;
+    let line = read_line(stream)?;
+
+    #[cfg(debug_assertions)]
+    debug!("Hello {line}");
+
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+        Some("HELLO") => parts.next().map(|v| v.to_string()),
+        _ => None,
+    }
+}
+
+/// Round trip of a `PING <timestamp>` / `PONG <timestamp>` exchange in
+/// microseconds, 0 on failure.
+fn request_tcp_ping(stream: &mut Box<dyn GenericStream>) -> u128 {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let request = format!("PING {timestamp}\n").into_bytes();
+
+    let now = Instant::now();
+    if stream.write_all(&request).is_err() {
+        return 0;
+    }
+    match read_line(stream) {
+        Some(line) if line.starts_with("PONG") => now.elapsed().as_micros(),
+        _r => {
+            #[cfg(debug_assertions)]
+            debug!("Ping {_r:?}");
+
+            0
+        }
+    }
+}
+
+fn request_tcp_quit(stream: &mut Box<dyn GenericStream>) {
+    let _ = stream.write_all(b"QUIT\n");
+}
+
+fn request_tcp_download(address: SocketAddr, url: Url, counter: LoadWorker) {
+    let data_size = 15 * 1024 * 1024 * 1024_u128;
     let mut buffer = [0; 65536];
 
-    let url = Url::parse("http://bench.im").unwrap();
     let mut stream = match make_connection(&address, &url) {
         Ok(s) => s,
         Err(_) => {
-            counter.wait();
+            counter.give_up();
             return;
         }
     };
 
+    if request_tcp_hello(&mut stream).is_none() {
+        counter.wait();
+        return;
+    }
+
     counter.wait();
 
     #[cfg(debug_assertions)]
@@ -225,24 +315,29 @@ fn request_tcp_download(address: SocketAddr, counter: Arc<LoadCounter>) {
             }
         }
     }
+
+    request_tcp_quit(&mut stream);
 }
 
-fn request_tcp_upload(address: SocketAddr, counter: Arc<LoadCounter>) {
-    let data_size = 15 * 1024 * 1024 * 1024 as u128;
+fn request_tcp_upload(address: SocketAddr, url: Url, counter: LoadWorker) {
+    let data_size = 15 * 1024 * 1024 * 1024_u128;
     let request_chunk = "0123456789AaBbCcDdEeFfGgHhIiJjKkLlMmNnOoPpQqRrSsTtUuVvWwXxYyZz-="
         .repeat(1024)
         .into_bytes();
 
-    let url = Url::parse("http://bench.im").unwrap();
-
     let mut stream = match make_connection(&address, &url) {
         Ok(s) => s,
         Err(_) => {
-            counter.wait();
+            counter.give_up();
             return;
         }
     };
 
+    if request_tcp_hello(&mut stream).is_none() {
+        counter.wait();
+        return;
+    }
+
     counter.wait();
 
     #[cfg(debug_assertions)]
@@ -261,6 +356,8 @@ fn request_tcp_upload(address: SocketAddr, counter: Arc<LoadCounter>) {
         }
     }
 
+    // The upload body is still open when the test ends, so a `QUIT` here would
+    // be read as payload; the connection is just dropped instead.
     while !counter.is_end() {
         match stream.write(&request_chunk) {
             Ok(size) => {
@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName};
+use rustls_pemfile::Item;
+
+static CONFIG: OnceLock<ClientConfig> = OnceLock::new();
+/// The shared configuration for every ALPN protocol list asked for so far.
+static ALPN_CONFIGS: Mutex<Vec<AlpnConfig>> = Mutex::new(vec![]);
+
+type AlpnConfig = (Vec<Vec<u8>>, Arc<ClientConfig>);
+
+/// How TLS servers are verified and how the client authenticates itself.
+#[derive(Default)]
+pub struct TlsOptions {
+    /// PEM files with CA certificates trusted next to the built-in roots.
+    pub ca_files: Vec<String>,
+    /// Accept any server certificate, for lab servers only.
+    pub insecure: bool,
+    /// PEM certificate chain and private key presented for mTLS.
+    pub client_cert: Option<(String, String)>,
+}
+
+/// Accepts any server certificate, for servers whose certificate cannot be
+/// verified such as the self-signed one of the bim QUIC listener.
+pub struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Build the TLS configuration used by every connection from `options`.
+/// Only the first call has an effect, and it has to come before the first
+/// connection.
+pub fn set_tls_options(options: TlsOptions) -> Result<(), String> {
+    let config = build_config(&options)?;
+
+    CONFIG
+        .set(config)
+        .map_err(|_| String::from("tls options already set"))
+}
+
+/// The shared client configuration offering `alpn` protocols.
+pub fn client_config(alpn: Vec<Vec<u8>>) -> Arc<ClientConfig> {
+    let mut configs = ALPN_CONFIGS.lock().unwrap();
+    if let Some((_, config)) = configs.iter().find(|(a, _)| *a == alpn) {
+        return config.clone();
+    }
+
+    let base = CONFIG.get_or_init(|| build_config(&TlsOptions::default()).unwrap());
+    let mut config = base.clone();
+    config.alpn_protocols = alpn.clone();
+
+    let config = Arc::new(config);
+    configs.push((alpn, config.clone()));
+    config
+}
+
+fn build_config(options: &TlsOptions) -> Result<ClientConfig, String> {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+
+    for path in &options.ca_files {
+        let certs = read_certs(path)?;
+        let (added, _) = root_store.add_parsable_certificates(&certs);
+        if added == 0 {
+            return Err(format!("{path}: no CA certificate found"));
+        }
+    }
+
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store);
+
+    let mut config = match &options.client_cert {
+        Some((cert_path, key_path)) => {
+            let certs = read_certs(cert_path)?
+                .into_iter()
+                .map(Certificate)
+                .collect();
+            let key = read_key(key_path)?;
+            builder
+                .with_single_cert(certs, key)
+                .map_err(|e| format!("{cert_path}: {e}"))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    if options.insecure {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoVerification));
+    }
+
+    Ok(config)
+}
+
+fn read_certs(path: &str) -> Result<Vec<Vec<u8>>, String> {
+    let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    rustls_pemfile::certs(&mut BufReader::new(file)).map_err(|e| format!("{path}: {e}"))
+}
+
+fn read_key(path: &str) -> Result<PrivateKey, String> {
+    let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    let items =
+        rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(|e| format!("{path}: {e}"))?;
+
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("{path}: no private key found"))
+}
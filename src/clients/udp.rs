@@ -0,0 +1,259 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use url::Url;
+
+use crate::clients::base::{get_address, load_duration, measure_latency, AddressFamily, Client};
+use crate::clients::probe::request_udp_ping;
+use crate::clients::source;
+use crate::packet::{
+    now_micros, send_paced, Packet, PacketCounter, KIND_CHALLENGE, KIND_DATA, KIND_DOWNLOAD,
+    KIND_END, KIND_REPORT, KIND_REPORT_REQUEST, PACKET_SIZE,
+};
+use crate::utils::{LatencyStats, PacketStats, SpeedTestResult};
+
+const TEST_DURATION: Duration = Duration::from_secs(10);
+
+pub struct UdpClient {
+    url: Url,
+    bitrate: u64,
+
+    address: SocketAddr,
+
+    upload: f64,
+    upload_status: String,
+    download: f64,
+    download_status: String,
+    latency: f64,
+    jitter: f64,
+    latency_stats: Option<LatencyStats>,
+    upload_packets: PacketStats,
+    download_packets: PacketStats,
+}
+
+impl UdpClient {
+    pub fn build(url: String, family: AddressFamily, bitrate: u64) -> Option<Box<dyn Client>> {
+        let url = Url::parse(&url).ok()?;
+
+        let address = get_address(&url, family)?;
+
+        #[cfg(debug_assertions)]
+        debug!("IP address {address}");
+
+        let r = "取消".to_owned();
+        let packets = PacketStats {
+            loss: 0.0,
+            reordered: 0.0,
+            jitter: 0.0,
+        };
+        Some(Box::new(Self {
+            url,
+            bitrate,
+            address,
+            upload: 0.0,
+            upload_status: r.clone(),
+            download: 0.0,
+            download_status: r,
+            latency: 0.0,
+            jitter: 0.0,
+            latency_stats: None,
+            upload_packets: packets.clone(),
+            download_packets: packets,
+        }))
+    }
+
+    fn make_socket(&self) -> Option<UdpSocket> {
+        let socket = source::udp_bind(&self.address).ok()?;
+        socket.connect(self.address).ok()?;
+        socket.set_read_timeout(Some(Duration::from_secs(1))).ok()?;
+        Some(socket)
+    }
+}
+
+impl Client for UdpClient {
+    fn ping(&mut self) -> bool {
+        let socket = match self.make_socket() {
+            Some(s) => s,
+            None => return false,
+        };
+
+        let stats = measure_latency(&self.address, &self.url, |count| {
+            request_udp_ping(&socket, count)
+        });
+
+        self.latency = stats.min;
+        self.jitter = stats.jitter;
+
+        let ok = stats.failed < stats.samples;
+        self.latency_stats = Some(stats);
+        ok
+    }
+
+    fn upload(&mut self) -> bool {
+        let socket = match self.make_socket() {
+            Some(s) => s,
+            None => return false,
+        };
+        let session = now_micros() as u32;
+
+        let sent = send_paced(
+            session,
+            self.bitrate,
+            test_duration(),
+            PACKET_SIZE,
+            |data| socket.send(data),
+        );
+        thread::sleep(Duration::from_millis(500));
+
+        let mut buffer = [0; 1500];
+        let request = Packet::new(KIND_REPORT_REQUEST, session);
+        let size = request.encode(&mut buffer);
+
+        for _ in 0..3 {
+            if socket.send(&buffer[..size]).is_err() {
+                continue;
+            }
+
+            let report = match socket.recv(&mut buffer) {
+                Ok(size) => Packet::decode(&buffer[..size]),
+                Err(_) => None,
+            };
+            if let Some(report) = report.filter(|p| p.kind == KIND_REPORT && p.session == session) {
+                let received = report.seq;
+
+                #[cfg(debug_assertions)]
+                debug!("Upload sent {sent} received {received}");
+
+                self.upload =
+                    (received * PACKET_SIZE as u64 * 8) as f64 / test_duration().as_micros() as f64;
+                self.upload_packets =
+                    packet_stats(sent, received, report.timestamp, report.value as f64);
+                self.upload_status = packet_status(received);
+                return true;
+            }
+        }
+
+        self.upload_status = "失败".to_string();
+        false
+    }
+
+    fn download(&mut self) -> bool {
+        let socket = match self.make_socket() {
+            Some(s) => s,
+            None => return false,
+        };
+        let session = now_micros() as u32;
+
+        let mut buffer = [0; 65536];
+        let mut request = Packet::new(KIND_DOWNLOAD, session);
+        request.seq = self.bitrate;
+        request.timestamp = test_duration().as_millis() as u64;
+        request.value = PACKET_SIZE as u64;
+
+        let mut counter = PacketCounter::default();
+        let mut sent = None;
+        let mut first = None;
+        let mut last = Instant::now();
+
+        'request: for _ in 0..3 {
+            let size = request.encode(&mut buffer);
+            if socket.send(&buffer[..size]).is_err() {
+                continue;
+            }
+
+            loop {
+                let size = match socket.recv(&mut buffer) {
+                    Ok(size) => size,
+                    Err(_) if counter.received > 0 => break 'request,
+                    Err(_) => continue 'request,
+                };
+                let arrival = now_micros();
+
+                let packet = match Packet::decode(&buffer[..size]) {
+                    Some(p) if p.session == session => p,
+                    _ => continue,
+                };
+
+                match packet.kind {
+                    KIND_CHALLENGE => {
+                        request.token = packet.token;
+                        continue 'request;
+                    }
+                    KIND_DATA => {
+                        counter.record(packet.seq, packet.timestamp, arrival);
+                        first.get_or_insert_with(Instant::now);
+                        last = Instant::now();
+                    }
+                    KIND_END => {
+                        sent = Some(packet.seq);
+                        break 'request;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let received = counter.received;
+        let sent = sent.unwrap_or_else(|| counter.expected());
+
+        #[cfg(debug_assertions)]
+        debug!("Download sent {sent} received {received}");
+
+        if let Some(first) = first {
+            let used = last.duration_since(first).max(Duration::from_millis(1));
+            self.download = (received * PACKET_SIZE as u64 * 8) as f64 / used.as_micros() as f64;
+        }
+        self.download_packets = packet_stats(sent, received, counter.reordered, counter.jitter());
+        self.download_status = packet_status(received);
+
+        received > 0
+    }
+
+    fn result(&self) -> SpeedTestResult {
+        let mut r = SpeedTestResult::build(
+            self.upload,
+            self.upload_status.clone(),
+            self.download,
+            self.download_status.clone(),
+            self.latency,
+            self.jitter,
+        );
+        r.set_packet_stats(self.upload_packets.clone(), self.download_packets.clone());
+        if let Some(stats) = &self.latency_stats {
+            r.set_latency_stats(stats.clone());
+        }
+        r
+    }
+}
+
+fn test_duration() -> Duration {
+    load_duration().unwrap_or(TEST_DURATION)
+}
+
+fn packet_stats(sent: u64, received: u64, reordered: u64, jitter: f64) -> PacketStats {
+    let loss = match sent {
+        0 => 0.0,
+        _ => sent.saturating_sub(received) as f64 * 100.0 / sent as f64,
+    };
+    let reordered = match received {
+        0 => 0.0,
+        _ => reordered as f64 * 100.0 / received as f64,
+    };
+
+    PacketStats {
+        loss,
+        reordered,
+        jitter: jitter / 1_000.0,
+    }
+}
+
+fn packet_status(received: u64) -> String {
+    match received {
+        0 => String::from("断流"),
+        _ => String::from("正常"),
+    }
+}
@@ -1,3 +1,4 @@
 pub mod clients;
+mod packet;
 pub mod servers;
 pub mod utils;
@@ -0,0 +1,149 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const MAGIC: &[u8; 4] = b"BIMU";
+pub const HEADER_SIZE: usize = 41;
+pub const PACKET_SIZE: usize = 1200;
+
+pub const KIND_ECHO: u8 = 1;
+pub const KIND_DATA: u8 = 2;
+pub const KIND_REPORT_REQUEST: u8 = 3;
+pub const KIND_REPORT: u8 = 4;
+pub const KIND_DOWNLOAD: u8 = 5;
+pub const KIND_END: u8 = 6;
+pub const KIND_CHALLENGE: u8 = 7;
+
+/// Header of every datagram exchanged by the UDP client and server.
+///
+/// The meaning of `seq`, `timestamp` and `value` depends on `kind`:
+/// - `DATA`: sequence number and send time in microseconds.
+/// - `REPORT`: packets received, packets reordered and jitter in microseconds.
+/// - `DOWNLOAD`: bitrate in bit/s, duration in milliseconds and packet size.
+/// - `END`: packets sent.
+#[derive(Debug, Default)]
+pub struct Packet {
+    pub kind: u8,
+    pub session: u32,
+    pub seq: u64,
+    pub timestamp: u64,
+    pub value: u64,
+    pub token: u64,
+}
+
+impl Packet {
+    pub fn new(kind: u8, session: u32) -> Self {
+        Self {
+            kind,
+            session,
+            ..Default::default()
+        }
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> usize {
+        buffer[0..4].copy_from_slice(MAGIC);
+        buffer[4] = self.kind;
+        buffer[5..9].copy_from_slice(&self.session.to_be_bytes());
+        buffer[9..17].copy_from_slice(&self.seq.to_be_bytes());
+        buffer[17..25].copy_from_slice(&self.timestamp.to_be_bytes());
+        buffer[25..33].copy_from_slice(&self.value.to_be_bytes());
+        buffer[33..41].copy_from_slice(&self.token.to_be_bytes());
+        HEADER_SIZE
+    }
+
+    pub fn decode(buffer: &[u8]) -> Option<Self> {
+        if buffer.len() < HEADER_SIZE || &buffer[0..4] != MAGIC {
+            return None;
+        }
+
+        let u64_at = |i: usize| u64::from_be_bytes(buffer[i..i + 8].try_into().unwrap());
+        Some(Self {
+            kind: buffer[4],
+            session: u32::from_be_bytes(buffer[5..9].try_into().unwrap()),
+            seq: u64_at(9),
+            timestamp: u64_at(17),
+            value: u64_at(25),
+            token: u64_at(33),
+        })
+    }
+}
+
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64
+}
+
+/// Receiver side accounting of a stream of sequenced datagrams.
+#[derive(Debug, Default)]
+pub struct PacketCounter {
+    pub received: u64,
+    pub reordered: u64,
+    max_seq: Option<u64>,
+    jitter: f64,
+    last_transit: Option<i64>,
+}
+
+impl PacketCounter {
+    pub fn record(&mut self, seq: u64, sent: u64, arrival: u64) {
+        self.received += 1;
+
+        match self.max_seq {
+            Some(max) if seq < max => self.reordered += 1,
+            _ => self.max_seq = Some(seq),
+        }
+
+        // RFC 3550 section 6.4.1, clock offset between hosts cancels out.
+        let transit = arrival as i64 - sent as i64;
+        if let Some(last) = self.last_transit {
+            let d = (transit - last).abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.last_transit = Some(transit);
+    }
+
+    /// Highest sequence number seen plus one, the best guess of packets sent
+    /// when the sender's total is unknown.
+    pub fn expected(&self) -> u64 {
+        self.max_seq.map(|s| s + 1).unwrap_or(0)
+    }
+
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+}
+
+/// Send `DATA` packets at `bitrate` bit/s for `duration`, returning the
+/// number of packets sent.
+pub fn send_paced<F>(
+    session: u32,
+    bitrate: u64,
+    duration: Duration,
+    size: usize,
+    mut send: F,
+) -> u64
+where
+    F: FnMut(&[u8]) -> io::Result<usize>,
+{
+    let size = size.clamp(HEADER_SIZE, 65507);
+    let mut buffer = vec![0; size];
+    let mut packet = Packet::new(KIND_DATA, session);
+    let packet_bits = (size * 8) as f64;
+
+    let now = Instant::now();
+    while now.elapsed() < duration {
+        let due = (now.elapsed().as_secs_f64() * bitrate as f64 / packet_bits) as u64 + 1;
+        while packet.seq < due {
+            packet.timestamp = now_micros();
+            packet.encode(&mut buffer);
+            if send(&buffer).is_err() {
+                return packet.seq;
+            }
+            packet.seq += 1;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    packet.seq
+}
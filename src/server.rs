@@ -1,7 +1,9 @@
 use getopts::Options;
 use std::env;
 
-use bim_core::servers::{HTTPServer, Server};
+#[cfg(feature = "quic")]
+use bim_core::servers::QUICServer;
+use bim_core::servers::{HTTPServer, Server, UDPServer};
 
 fn print_usage(program: &str, opts: Options) {
     let brief = format!("Usage: {} HOST:PORT [options]", program);
@@ -11,6 +13,9 @@ fn print_usage(program: &str, opts: Options) {
 fn get_server(server_name: &str, address: &str) -> Option<Box<dyn Server>> {
     match server_name {
         "http" => Some(Box::new(HTTPServer::build(address.to_string()).unwrap())),
+        "udp" => Some(Box::new(UDPServer::build(address.to_string())?)),
+        #[cfg(feature = "quic")]
+        "quic" => Some(Box::new(QUICServer::build(address.to_string())?)),
         _ => None,
     }
 }
@@ -20,12 +25,12 @@ fn main() {
     let program = args[0].clone();
 
     let mut opts = Options::new();
-    opts.optopt("s", "server", "set test server", "NAME");
+    opts.optopt("s", "server", "set test server: http, udp, quic", "NAME");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => {
-            println!("{}\n", f.to_string());
+            println!("{}\n", f);
             print_usage(&program, opts);
             return;
         }
@@ -37,7 +42,7 @@ fn main() {
     }
 
     let address = if !matches.free.is_empty() {
-        matches.free.get(0).unwrap()
+        matches.free.first().unwrap()
     } else {
         print_usage(&program, opts);
         return;
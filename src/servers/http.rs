@@ -15,12 +15,12 @@ pub struct HTTPServer {
 
 impl HTTPServer {
     pub fn build(address: String) -> Option<Self> {
-        let _ = match address.to_socket_addrs() {
+        match address.to_socket_addrs() {
             Ok(_) => {}
             Err(_) => return None,
         };
 
-        return Some(Self { address });
+        Some(Self { address })
     }
 }
 
@@ -57,7 +57,7 @@ impl Server for HTTPServer {
                 match method {
                     Method::Get => {
                         let mut counter = 0;
-                        let head = "HTTP/1.1 200\r\n\r\n".as_bytes();
+                        let head = "HTTP/1.1 200 OK\r\nContent-Length: 52428800\r\n\r\n".as_bytes();
                         let _ = writer.write_all(head);
 
                         while counter < 50 * 1024 * 1024 {
@@ -65,13 +65,22 @@ impl Server for HTTPServer {
                             counter += 65536;
                         }
                     }
+                    Method::Head => {
+                        let head = "HTTP/1.1 200 OK\r\nContent-Length: 52428800\r\n\r\n".as_bytes();
+                        let _ = writer.write_all(head);
+                        let _ = writer.flush();
+                    }
                     Method::Post => {
-                        let head = "HTTP/1.1 200\r\n\r\n".as_bytes();
+                        let head = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".as_bytes();
                         let _ = writer.write_all(head);
+                        let _ = writer.flush();
                     }
                     _ => {
-                        let head = "HTTP/1.1 500\r\n\r\n".as_bytes();
+                        let head =
+                            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+                                .as_bytes();
                         let _ = writer.write_all(head);
+                        let _ = writer.flush();
                     }
                 }
             });
@@ -1,5 +1,11 @@
 mod base;
 mod http;
+#[cfg(feature = "quic")]
+mod quic;
+mod udp;
 
 pub use base::Server;
 pub use http::HTTPServer;
+#[cfg(feature = "quic")]
+pub use quic::QUICServer;
+pub use udp::UDPServer;
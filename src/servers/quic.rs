@@ -0,0 +1,119 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use quinn::{Connecting, Endpoint, RecvStream, SendStream, TransportConfig, VarInt};
+
+use crate::servers::Server;
+
+const ALPN: &[u8] = b"bim";
+const WINDOW_SIZE: u32 = 16 * 1024 * 1024;
+
+pub struct QUICServer {
+    address: SocketAddr,
+}
+
+impl QUICServer {
+    pub fn build(address: String) -> Option<Self> {
+        let address = address.to_socket_addrs().ok()?.next()?;
+
+        Some(Self { address })
+    }
+
+    fn config() -> Option<quinn::ServerConfig> {
+        let cert = rcgen::generate_simple_self_signed(vec!["bim".to_string()]).ok()?;
+        let key = rustls::PrivateKey(cert.serialize_private_key_der());
+        let cert = rustls::Certificate(cert.serialize_der().ok()?);
+
+        let mut crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)
+            .ok()?;
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+        let mut transport = TransportConfig::default();
+        transport
+            .stream_receive_window(VarInt::from_u32(WINDOW_SIZE))
+            .receive_window(VarInt::from_u32(WINDOW_SIZE * 4))
+            .send_window(WINDOW_SIZE as u64 * 4);
+
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        config.transport_config(Arc::new(transport));
+        Some(config)
+    }
+}
+
+impl Server for QUICServer {
+    fn run(&mut self) -> bool {
+        let config = match Self::config() {
+            Some(c) => c,
+            None => return false,
+        };
+
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(r) => r,
+            Err(_) => return false,
+        };
+
+        runtime.block_on(async {
+            let endpoint = match Endpoint::server(config, self.address) {
+                Ok(e) => e,
+                Err(_e) => {
+                    #[cfg(debug_assertions)]
+                    debug!("Start Failed {_e}");
+
+                    return false;
+                }
+            };
+
+            while let Some(connecting) = endpoint.accept().await {
+                tokio::spawn(handle_connection(connecting));
+            }
+
+            true
+        })
+    }
+}
+
+async fn handle_connection(connecting: Connecting) {
+    let conn = match connecting.await {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+
+    #[cfg(debug_assertions)]
+    debug!("Connection {}", conn.remote_address());
+
+    while let Ok((send, recv)) = conn.accept_bi().await {
+        tokio::spawn(handle_stream(send, recv));
+    }
+}
+
+async fn handle_stream(mut send: SendStream, mut recv: RecvStream) {
+    let mut buffer = vec![0; 65536];
+    let mut line = Vec::new();
+
+    // Every stream opens with a newline terminated command.
+    while !line.contains(&b'\n') {
+        match recv.read(&mut buffer).await {
+            Ok(Some(size)) if line.len() < 64 => line.extend_from_slice(&buffer[..size]),
+            _ => return,
+        }
+    }
+
+    if line.starts_with(b"DOWNLOAD\n") {
+        let data = "0123456789AaBbCcDdEeFfGgHhIiJjKkLlMmNnOoPpQqRrSsTtUuVvWwXxYyZz-="
+            .repeat(1024)
+            .into_bytes();
+
+        while send.write_all(&data).await.is_ok() {}
+    } else if line.starts_with(b"UPLOAD\n") {
+        while let Ok(Some(_)) = recv.read(&mut buffer).await {}
+    }
+}
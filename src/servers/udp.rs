@@ -0,0 +1,175 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(debug_assertions)]
+use log::debug;
+
+use crate::packet::{
+    now_micros, send_paced, Packet, PacketCounter, KIND_CHALLENGE, KIND_DATA, KIND_DOWNLOAD,
+    KIND_ECHO, KIND_END, KIND_REPORT, KIND_REPORT_REQUEST,
+};
+use crate::servers::Server;
+
+const MAX_BITRATE: u64 = 10_000_000_000;
+const MAX_DURATION: u64 = 60_000;
+const MAX_SESSIONS: usize = 4096;
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_SENDERS: usize = 64;
+/// Tokens are valid for the period they were handed out in and the next.
+const TOKEN_PERIOD: u64 = 30_000_000;
+
+pub struct UDPServer {
+    address: String,
+    secret: RandomState,
+}
+
+impl UDPServer {
+    pub fn build(address: String) -> Option<Self> {
+        match address.to_socket_addrs() {
+            Ok(_) => {}
+            Err(_) => return None,
+        };
+
+        Some(Self {
+            address,
+            secret: RandomState::new(),
+        })
+    }
+
+    /// Token proving the sender of a `DOWNLOAD` can receive at its source
+    /// address, so a spoofed request cannot turn the server into a flood.
+    fn token(&self, address: &SocketAddr, session: u32, period: u64) -> u64 {
+        let mut hasher = self.secret.build_hasher();
+        address.hash(&mut hasher);
+        session.hash(&mut hasher);
+        period.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl Server for UDPServer {
+    fn run(&mut self) -> bool {
+        let socket = match UdpSocket::bind(&self.address) {
+            Ok(s) => s,
+            Err(_e) => {
+                #[cfg(debug_assertions)]
+                debug!("Start Failed {_e}");
+
+                return false;
+            }
+        };
+
+        let mut buffer = [0; 65536];
+        let mut sessions: HashMap<(SocketAddr, u32), (PacketCounter, Instant)> = HashMap::new();
+        // Downloads already started, so a retry or replay of a token does not
+        // start another sender.
+        let mut served: HashMap<(SocketAddr, u32), Instant> = HashMap::new();
+        let senders = Arc::new(AtomicUsize::new(0));
+
+        loop {
+            let (size, address) = match socket.recv_from(&mut buffer) {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            let arrival = now_micros();
+
+            let packet = match Packet::decode(&buffer[..size]) {
+                Some(p) => p,
+                None => continue,
+            };
+
+            match packet.kind {
+                KIND_ECHO => {
+                    let _ = socket.send_to(&buffer[..size], address);
+                }
+                KIND_DATA => {
+                    if sessions.len() >= MAX_SESSIONS {
+                        sessions.retain(|_, (_, last_seen)| last_seen.elapsed() < SESSION_TIMEOUT);
+                    }
+
+                    let (counter, last_seen) = sessions
+                        .entry((address, packet.session))
+                        .or_insert_with(|| (PacketCounter::default(), Instant::now()));
+                    counter.record(packet.seq, packet.timestamp, arrival);
+                    *last_seen = Instant::now();
+                }
+                KIND_REPORT_REQUEST => {
+                    sessions.retain(|_, (_, last_seen)| last_seen.elapsed() < SESSION_TIMEOUT);
+
+                    let mut report = Packet::new(KIND_REPORT, packet.session);
+                    if let Some((counter, _)) = sessions.get(&(address, packet.session)) {
+                        report.seq = counter.received;
+                        report.timestamp = counter.reordered;
+                        report.value = counter.jitter() as u64;
+                    }
+
+                    let size = report.encode(&mut buffer);
+                    let _ = socket.send_to(&buffer[..size], address);
+                }
+                KIND_DOWNLOAD => {
+                    let period = now_micros() / TOKEN_PERIOD;
+                    let valid = [period, period.saturating_sub(1)]
+                        .iter()
+                        .any(|p| packet.token == self.token(&address, packet.session, *p));
+                    if !valid {
+                        let mut challenge = Packet::new(KIND_CHALLENGE, packet.session);
+                        challenge.token = self.token(&address, packet.session, period);
+
+                        let size = challenge.encode(&mut buffer);
+                        let _ = socket.send_to(&buffer[..size], address);
+                        continue;
+                    }
+
+                    let token_lifetime = Duration::from_micros(2 * TOKEN_PERIOD);
+                    served.retain(|_, started| started.elapsed() < token_lifetime);
+                    if served.contains_key(&(address, packet.session))
+                        || served.len() >= MAX_SESSIONS
+                        || senders.load(Ordering::Relaxed) >= MAX_SENDERS
+                    {
+                        continue;
+                    }
+                    served.insert((address, packet.session), Instant::now());
+
+                    let sender = match socket.try_clone() {
+                        Ok(s) => s,
+                        Err(_) => continue,
+                    };
+                    let session = packet.session;
+                    let bitrate = packet.seq.min(MAX_BITRATE);
+                    let duration = Duration::from_millis(packet.timestamp.min(MAX_DURATION));
+                    let size = packet.value as usize;
+
+                    #[cfg(debug_assertions)]
+                    debug!("Download {address} {bitrate}bps {duration:?}");
+
+                    let active = senders.clone();
+                    active.fetch_add(1, Ordering::Relaxed);
+                    thread::spawn(move || {
+                        let sent = send_paced(session, bitrate, duration, size, |data| {
+                            sender.send_to(data, address)
+                        });
+
+                        let mut end = Packet::new(KIND_END, session);
+                        end.seq = sent;
+
+                        let mut buffer = [0; 64];
+                        let size = end.encode(&mut buffer);
+                        for _ in 0..3 {
+                            let _ = sender.send_to(&buffer[..size], address);
+                            thread::sleep(Duration::from_millis(10));
+                        }
+
+                        active.fetch_sub(1, Ordering::Relaxed);
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -9,7 +9,7 @@ pub fn justify_name(name: &str, length: u8, left_right: bool) -> String {
 
     if width < length as usize {
         let space_count = length as usize - width;
-        let spaces = " ".repeat(space_count as usize);
+        let spaces = " ".repeat(space_count);
         if left_right {
             justified_name += spaces.as_str();
         } else {
@@ -19,6 +19,307 @@ pub fn justify_name(name: &str, length: u8, left_right: bool) -> String {
     justified_name
 }
 
+/// Datagram delivery of one direction of a UDP test.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PacketStats {
+    #[serde(serialize_with = "serialize_f64")]
+    pub loss: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub reordered: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub jitter: f64,
+}
+
+impl fmt::Display for PacketStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Loss {:.1}%, Reordered {:.1}%, Jitter {:.1}",
+            self.loss, self.reordered, self.jitter
+        )
+    }
+}
+
+/// Total bytes over total time of a test made of many requests, next to the
+/// per-request figure reported as the main result.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AggregateSpeed {
+    #[serde(serialize_with = "serialize_f64")]
+    pub upload: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub download: f64,
+}
+
+impl fmt::Display for AggregateSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Aggregate Upload {:.1}Mbps, Aggregate Download {:.1}Mbps",
+            self.upload, self.download
+        )
+    }
+}
+
+/// Seconds the upload and download were measured for, which vary with
+/// adaptive duration.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TestDuration {
+    #[serde(serialize_with = "serialize_f64")]
+    pub upload: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub download: f64,
+}
+
+impl fmt::Display for TestDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Duration Upload {:.1}s, Duration Download {:.1}s",
+            self.upload, self.download
+        )
+    }
+}
+
+/// Connection counts the upload and download saturated at when ramping up.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Saturation {
+    pub upload: u8,
+    pub download: u8,
+}
+
+impl fmt::Display for Saturation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Saturation Upload {} connections, Saturation Download {} connections",
+            self.upload, self.download
+        )
+    }
+}
+
+/// What one worker connection of a load test transferred.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FlowStats {
+    pub bytes: u64,
+    /// Seconds without progress while measuring.
+    #[serde(serialize_with = "serialize_f64")]
+    pub stall: f64,
+    pub reconnects: u32,
+    /// The worker gave up before the test ended, or never connected.
+    pub died_early: bool,
+}
+
+/// The worker connections of a load test and how evenly they shared it.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct FlowReport {
+    /// Jain's fairness index of the bytes, from 1/n when one connection
+    /// carried everything to 1 when all carried the same.
+    #[serde(serialize_with = "serialize_f64_2")]
+    pub fairness: f64,
+    pub flows: Vec<FlowStats>,
+}
+
+impl FlowReport {
+    pub fn build(flows: Vec<FlowStats>) -> FlowReport {
+        let sum: f64 = flows.iter().map(|f| f.bytes as f64).sum();
+        let squares: f64 = flows.iter().map(|f| (f.bytes as f64).powi(2)).sum();
+        let fairness = if squares > 0.0 {
+            sum * sum / (flows.len() as f64 * squares)
+        } else {
+            0.0
+        };
+
+        FlowReport { fairness, flows }
+    }
+}
+
+impl fmt::Display for FlowReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let died = self.flows.iter().filter(|f| f.died_early).count();
+        let reconnects: u32 = self.flows.iter().map(|f| f.reconnects).sum();
+        let stall = self.flows.iter().map(|f| f.stall).fold(0.0, f64::max);
+        write!(
+            f,
+            "{} Flows, Fairness {:.2}, Died Early {}, Reconnects {}, Max Stall {:.1}s",
+            self.flows.len(),
+            self.fairness,
+            died,
+            reconnects,
+            stall
+        )
+    }
+}
+
+/// The middle of `values`, or the mean of the two in the middle, 0 if there
+/// are none.
+pub fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    let n = sorted.len();
+    match n {
+        0 => 0.0,
+        n if n % 2 == 0 => (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0,
+        _ => sorted[n / 2],
+    }
+}
+
+/// Round trip times of the latency probes in milliseconds.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LatencyStats {
+    pub samples: u32,
+    pub failed: u32,
+    #[serde(serialize_with = "serialize_f64")]
+    pub min: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub mean: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub median: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub p90: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub p99: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    pub stddev: f64,
+    /// Mean amount the successful probes took over the fastest one.
+    #[serde(serialize_with = "serialize_f64")]
+    pub jitter: f64,
+    /// RFC 3550 interarrival jitter over consecutive successful probes.
+    #[serde(serialize_with = "serialize_f64")]
+    pub interarrival_jitter: f64,
+}
+
+impl LatencyStats {
+    /// Statistics of the `rtts` of the successful probes, in the order they
+    /// were taken, out of `samples` probes.
+    pub fn build(rtts: &[f64], samples: u32) -> Self {
+        let mut stats = LatencyStats {
+            samples,
+            failed: samples.saturating_sub(rtts.len() as u32),
+            ..Default::default()
+        };
+        if rtts.is_empty() {
+            return stats;
+        }
+
+        let mut interarrival_jitter = 0.0;
+        for pair in rtts.windows(2) {
+            interarrival_jitter += ((pair[1] - pair[0]).abs() - interarrival_jitter) / 16.0;
+        }
+
+        let mut sorted = rtts.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let n = sorted.len();
+        // Nearest rank.
+        let percentile = |p: f64| sorted[((p / 100.0 * n as f64).ceil() as usize).clamp(1, n) - 1];
+
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        let variance = sorted.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n as f64;
+
+        stats.min = sorted[0];
+        stats.mean = mean;
+        stats.median = median(&sorted);
+        stats.p90 = percentile(90.0);
+        stats.p99 = percentile(99.0);
+        stats.stddev = variance.sqrt();
+        // The fastest probe adds nothing, so it is left out of the count.
+        if n > 1 {
+            stats.jitter = sorted.iter().map(|r| r - sorted[0]).sum::<f64>() / (n - 1) as f64;
+        }
+        stats.interarrival_jitter = interarrival_jitter;
+        stats
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Latency Min {:.1}, Mean {:.1}, Median {:.1}, P90 {:.1}, P99 {:.1}, Stddev {:.1}, Jitter {:.1}, Interarrival Jitter {:.1}, Failed {}/{}",
+            self.min,
+            self.mean,
+            self.median,
+            self.p90,
+            self.p99,
+            self.stddev,
+            self.jitter,
+            self.interarrival_jitter,
+            self.failed,
+            self.samples
+        )
+    }
+}
+
+/// How one connection to the server was set up, times in milliseconds.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConnectionInfo {
+    #[serde(serialize_with = "serialize_f64")]
+    pub connect: f64,
+    #[serde(
+        serialize_with = "serialize_option_f64",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub handshake: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cipher_suite: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ConnectionInfo {
+    pub fn new(connect: f64) -> Self {
+        Self {
+            connect,
+            handshake: None,
+            protocol: None,
+            cipher_suite: None,
+            alpn: None,
+            error: None,
+        }
+    }
+}
+
+/// Summary of the connections of a test: how many, average setup times and
+/// what the last TLS handshake negotiated.
+struct ConnectionsSummary<'a>(&'a [ConnectionInfo]);
+
+impl fmt::Display for ConnectionsSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let connections = self.0;
+        let connect = connections.iter().map(|c| c.connect).sum::<f64>() / connections.len() as f64;
+        write!(
+            f,
+            "Connections {}, Connect {:.1}",
+            connections.len(),
+            connect
+        )?;
+
+        let handshakes: Vec<f64> = connections.iter().filter_map(|c| c.handshake).collect();
+        if !handshakes.is_empty() {
+            let handshake = handshakes.iter().sum::<f64>() / handshakes.len() as f64;
+            write!(f, ", TLS Handshake {handshake:.1}")?;
+        }
+        if let Some(c) = connections.iter().rev().find(|c| c.handshake.is_some()) {
+            for detail in [&c.protocol, &c.cipher_suite, &c.alpn]
+                .into_iter()
+                .flatten()
+            {
+                write!(f, " {detail}")?;
+            }
+        }
+
+        let failed = connections.iter().filter(|c| c.error.is_some()).count();
+        if failed > 0 {
+            write!(f, ", TLS Failed {failed}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SpeedTestResult {
     #[serde(serialize_with = "serialize_f64")]
@@ -31,6 +332,32 @@ pub struct SpeedTestResult {
     latency: f64,
     #[serde(serialize_with = "serialize_f64")]
     jitter: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_packets: Option<PacketStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_packets: Option<PacketStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregate: Option<AggregateSpeed>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<TestDuration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    saturation: Option<Saturation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_flows: Option<FlowReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_flows: Option<FlowReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_stats: Option<LatencyStats>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    connections: Vec<ConnectionInfo>,
 }
 
 fn serialize_f64<S>(x: &f64, serializer: S) -> Result<S::Ok, S::Error>
@@ -41,6 +368,24 @@ where
     serializer.serialize_str(&s)
 }
 
+fn serialize_f64_2<S>(x: &f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let s = format!("{:.2}", x);
+    serializer.serialize_str(&s)
+}
+
+fn serialize_option_f64<S>(x: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match x {
+        Some(x) => serialize_f64(x, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
 impl SpeedTestResult {
     pub fn build(
         upload: f64,
@@ -50,14 +395,73 @@ impl SpeedTestResult {
         latency: f64,
         jitter: f64,
     ) -> SpeedTestResult {
-        return SpeedTestResult {
+        SpeedTestResult {
             upload,
             upload_status,
             download,
             download_status,
             latency,
             jitter,
-        };
+            client_ip: None,
+            server_version: None,
+            upload_packets: None,
+            download_packets: None,
+            aggregate: None,
+            duration: None,
+            saturation: None,
+            upload_flows: None,
+            download_flows: None,
+            upload_url: None,
+            download_url: None,
+            latency_stats: None,
+            connections: vec![],
+        }
+    }
+
+    pub fn set_client_ip(&mut self, client_ip: String) {
+        self.client_ip = Some(client_ip);
+    }
+
+    pub fn set_server_version(&mut self, server_version: String) {
+        self.server_version = Some(server_version);
+    }
+
+    pub fn set_packet_stats(&mut self, upload: PacketStats, download: PacketStats) {
+        self.upload_packets = Some(upload);
+        self.download_packets = Some(download);
+    }
+
+    pub fn set_aggregate(&mut self, aggregate: AggregateSpeed) {
+        self.aggregate = Some(aggregate);
+    }
+
+    pub fn set_duration(&mut self, duration: TestDuration) {
+        self.duration = Some(duration);
+    }
+
+    pub fn set_saturation(&mut self, saturation: Saturation) {
+        self.saturation = Some(saturation);
+    }
+
+    /// Record the worker connections of each load test, if it had any.
+    pub fn set_flows(&mut self, upload: FlowReport, download: FlowReport) {
+        self.upload_flows = Some(upload).filter(|f| !f.flows.is_empty());
+        self.download_flows = Some(download).filter(|f| !f.flows.is_empty());
+    }
+
+    /// Record the URLs actually measured, after any redirects.
+    pub fn set_urls(&mut self, upload_url: String, download_url: String) {
+        self.upload_url = Some(upload_url);
+        self.download_url = Some(download_url);
+    }
+
+    pub fn set_latency_stats(&mut self, latency_stats: LatencyStats) {
+        self.latency_stats = Some(latency_stats);
+    }
+
+    /// Record how the connections of the test were set up.
+    pub fn set_connections(&mut self, connections: Vec<ConnectionInfo>) {
+        self.connections = connections;
     }
 
     pub fn text(&self) -> String {
@@ -83,6 +487,87 @@ impl fmt::Display for SpeedTestResult {
             self.download_status,
             self.latency,
             self.jitter
-        )
+        )?;
+        if let Some(client_ip) = &self.client_ip {
+            write!(f, ", IP {client_ip}")?;
+        }
+        if let Some(server_version) = &self.server_version {
+            write!(f, ", Server {server_version}")?;
+        }
+        if let Some(upload_packets) = &self.upload_packets {
+            write!(f, ", Upload {upload_packets}")?;
+        }
+        if let Some(download_packets) = &self.download_packets {
+            write!(f, ", Download {download_packets}")?;
+        }
+        if let Some(aggregate) = &self.aggregate {
+            write!(f, ", {aggregate}")?;
+        }
+        if let Some(duration) = &self.duration {
+            write!(f, ", {duration}")?;
+        }
+        if let Some(saturation) = &self.saturation {
+            write!(f, ", {saturation}")?;
+        }
+        if let Some(upload_flows) = &self.upload_flows {
+            write!(f, ", Upload {upload_flows}")?;
+        }
+        if let Some(download_flows) = &self.download_flows {
+            write!(f, ", Download {download_flows}")?;
+        }
+        if let Some(upload_url) = &self.upload_url {
+            write!(f, ", Upload URL {upload_url}")?;
+        }
+        if let Some(download_url) = &self.download_url {
+            write!(f, ", Download URL {download_url}")?;
+        }
+        if let Some(latency_stats) = &self.latency_stats {
+            write!(f, ", {latency_stats}")?;
+        }
+        if !self.connections.is_empty() {
+            write!(f, ", {}", ConnectionsSummary(&self.connections))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flows(bytes: &[u64]) -> Vec<FlowStats> {
+        bytes
+            .iter()
+            .map(|&bytes| FlowStats {
+                bytes,
+                stall: 0.0,
+                reconnects: 0,
+                died_early: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_flows() {
+        let report = FlowReport::build(vec![]);
+        assert!(report.flows.is_empty());
+        assert_eq!(report.fairness, 0.0);
+    }
+
+    #[test]
+    fn equal_flows_are_fair() {
+        let report = FlowReport::build(flows(&[1_000, 1_000, 1_000, 1_000]));
+        assert_eq!(report.flows.len(), 4);
+        assert_eq!(report.fairness, 1.0);
+    }
+
+    #[test]
+    fn one_dominant_flow() {
+        // Jain's index falls to 1/n when one flow takes everything.
+        let report = FlowReport::build(flows(&[1_000, 0, 0, 0]));
+        assert_eq!(report.fairness, 0.25);
+
+        let report = FlowReport::build(flows(&[9_000, 1_000]));
+        assert!((report.fairness - 0.61).abs() < 0.01);
     }
 }